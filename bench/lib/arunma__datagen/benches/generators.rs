@@ -0,0 +1,96 @@
+//! Generation throughput on the bookstore schemas: every dataset of `schemas/schema_bookstore.yaml`
+//! generates `ROWS` rows per iteration, either compiling the columns for every record or value, as the
+//! sinks used to, or through generators compiled once per dataset. The CSV benchmarks write the same rows
+//! record by record and as columnar batches. Run with `cargo bench`.
+
+use bencher::{benchmark_group, benchmark_main, Bencher};
+use datagen::fakegen::{gen_record_for_schema, generate_fake_data, RecordGenerator};
+use datagen::schema::{MultiSchema, Schema};
+use datagen::sinks::csv_sink::{self, CsvOptions};
+use datagen::sinks::Sink;
+
+const ROWS: i64 = 1_000;
+
+fn bookstore() -> Vec<Schema> {
+    let multi_schema = MultiSchema::from_path("./schemas/schema_bookstore.yaml".to_string()).unwrap();
+    let mut schemas = multi_schema.ordered_schemas().unwrap();
+    for schema in schemas.iter_mut() {
+        schema.resolve_seeds(42);
+    }
+    schemas
+}
+
+fn per_record_schema_clones(b: &mut Bencher) {
+    let schemas = bookstore();
+    b.iter(|| {
+        for schema in &schemas {
+            for row in 0..ROWS {
+                bencher::black_box(gen_record_for_schema(schema.clone(), row).unwrap());
+            }
+        }
+    });
+}
+
+fn compiled_record_generators(b: &mut Bencher) {
+    let generators: Vec<RecordGenerator> = bookstore().iter().map(|schema| RecordGenerator::new(schema).unwrap()).collect();
+    b.iter(|| {
+        for generator in &generators {
+            for row in 0..ROWS {
+                bencher::black_box(generator.generate(row));
+            }
+        }
+    });
+}
+
+fn per_value_column_clones(b: &mut Bencher) {
+    let schemas = bookstore();
+    b.iter(|| {
+        for column in schemas.iter().flat_map(|schema| &schema.dataset.columns) {
+            for row in 0..ROWS {
+                bencher::black_box(generate_fake_data(column.clone(), row).unwrap());
+            }
+        }
+    });
+}
+
+fn compiled_column_generators(b: &mut Bencher) {
+    let generators: Vec<RecordGenerator> = bookstore().iter().map(|schema| RecordGenerator::new(schema).unwrap()).collect();
+    b.iter(|| {
+        for generator in generators.iter().flat_map(|generator| &generator.columns) {
+            for row in 0..ROWS {
+                bencher::black_box(generator.generate(row));
+            }
+        }
+    });
+}
+
+fn csv_records(b: &mut Bencher) {
+    let schemas = bookstore();
+    let generators: Vec<RecordGenerator> = schemas.iter().map(|schema| RecordGenerator::new(schema).unwrap()).collect();
+    b.iter(|| {
+        for (schema, generator) in schemas.iter().zip(&generators) {
+            let mut sink = csv_sink::sink(schema.clone(), std::io::sink(), b'|', CsvOptions::default()).unwrap();
+            for row in 0..ROWS {
+                sink.write(generator.generate(row)).unwrap();
+            }
+            sink.finish().unwrap();
+        }
+    });
+}
+
+fn csv_batches(b: &mut Bencher) {
+    let schemas = bookstore();
+    let generators: Vec<RecordGenerator> = schemas.iter().map(|schema| RecordGenerator::new(schema).unwrap()).collect();
+    b.iter(|| {
+        for (schema, generator) in schemas.iter().zip(&generators) {
+            let mut sink = csv_sink::sink(schema.clone(), std::io::sink(), b'|', CsvOptions::default()).unwrap();
+            sink.write_batch(generator.generate_batch(0, ROWS as usize).unwrap()).unwrap();
+            sink.finish().unwrap();
+        }
+    });
+}
+
+benchmark_group!(records, per_record_schema_clones, compiled_record_generators);
+benchmark_group!(values, per_value_column_clones, compiled_column_generators);
+benchmark_group!(csv, csv_records, csv_batches);
+benchmark_main!(records, values, csv);
@@ -0,0 +1,307 @@
+use crate::errors::DataGenError::WeirdCase;
+use crate::errors::*;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Compression of the output files. `zip`, `zip-stored` and `tar` are containers holding a batch of splits,
+/// the other codecs compress each split into a file of its own.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Codec {
+    None,
+    Gzip,
+    Zstd,
+    Lz4,
+    Zip,
+    ZipStored,
+    Tar,
+}
+
+impl FromStr for Codec {
+    type Err = DataGenError;
+
+    fn from_str(s: &str) -> DataGenResult<Codec> {
+        match s {
+            "none" => Ok(Codec::None),
+            "gzip" => Ok(Codec::Gzip),
+            "zstd" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            "zip" => Ok(Codec::Zip),
+            "zip-stored" => Ok(Codec::ZipStored),
+            "tar" => Ok(Codec::Tar),
+            _ => Err(WeirdCase { message: format!("Unknown compression {}, expected none, gzip, zstd, lz4, zip, zip-stored or tar", s) }),
+        }
+    }
+}
+
+impl Codec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+            Codec::Zip => "zip",
+            Codec::ZipStored => "zip-stored",
+            Codec::Tar => "tar",
+        }
+    }
+
+    /// Whether a file holds several splits, packed `zip_pack_batch_size` at a time
+    pub fn is_container(&self) -> bool {
+        matches!(self, Codec::Zip | Codec::ZipStored | Codec::Tar)
+    }
+
+    /// Suffix of the files, appended to the name of the split or batch they hold
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Codec::None => "",
+            Codec::Gzip => ".gz",
+            Codec::Zstd => ".zst",
+            Codec::Lz4 => ".lz4",
+            Codec::Zip | Codec::ZipStored => ".zip",
+            Codec::Tar => ".tar",
+        }
+    }
+
+    /// Accepted levels and the default one, `None` when the codec takes no level
+    fn levels(&self) -> Option<(RangeInclusive<i32>, i32)> {
+        match self {
+            Codec::Gzip => Some((0..=9, 6)),
+            Codec::Zstd => Some((1..=22, 3)),
+            Codec::Lz4 => Some((0..=12, 0)),
+            // Level 9 is what the ZIP files were always written with
+            Codec::Zip => Some((0..=9, 9)),
+            Codec::None | Codec::ZipStored | Codec::Tar => None,
+        }
+    }
+
+    /// Shell command writing split `entry` of file `file_name` to stdout, for `COPY ... FROM PROGRAM`.
+    /// `None` for uncompressed splits, which are read directly.
+    pub fn program(&self, file_name: &str, entry: &str) -> Option<String> {
+        match self {
+            Codec::None => None,
+            Codec::Gzip => Some(format!("gzip -dc \"{}\"", file_name)),
+            Codec::Zstd => Some(format!("zstd -dc \"{}\"", file_name)),
+            Codec::Lz4 => Some(format!("lz4 -dc \"{}\"", file_name)),
+            Codec::Zip | Codec::ZipStored => Some(format!("unzip -p \"{}\" \"{}\"", file_name, entry)),
+            Codec::Tar => Some(format!("tar -xOf \"{}\" \"{}\"", file_name, entry)),
+        }
+    }
+}
+
+/// Codec and level of the output files
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Compression {
+    pub codec: Codec,
+    /// Level of the codecs taking one
+    pub level: Option<i32>,
+}
+
+impl Compression {
+    /// Checks `level` against the codec's range, defaulting it when unset
+    pub fn new(codec: Codec, level: Option<i32>) -> DataGenResult<Compression> {
+        let level = match (codec.levels(), level) {
+            (Some((range, _)), Some(level)) if !range.contains(&level) => {
+                return Err(WeirdCase {
+                    message: format!("Compression level {} out of range for {}, expected {} to {}", level, codec.name(), range.start(), range.end())
+                });
+            }
+            (Some(_), Some(level)) => Some(level),
+            (Some((_, default)), None) => Some(default),
+            (None, Some(_)) => return Err(WeirdCase { message: format!("Compression {} takes no level", codec.name()) }),
+            (None, None) => None,
+        };
+        Ok(Compression { codec, level })
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression { codec: Codec::Zip, level: Some(9) }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.level {
+            Some(level) => write!(f, "{} level {}", self.codec.name(), level),
+            None => write!(f, "{}", self.codec.name()),
+        }
+    }
+}
+
+/// Writes the splits `entries` into a new file at `path`, calling `write_entry` with the index of each split
+/// and the writer its content goes to. Entries are streamed through the compressor, so memory stays flat
+/// whatever their size; ZIP and tar entries may exceed 4 GiB. Only container codecs hold several splits.
+pub fn write_file<F>(path: &str, compression: &Compression, entries: &[String], mut write_entry: F) -> DataGenResult<()>
+where
+    F: FnMut(usize, &mut (dyn Write + Send)) -> DataGenResult<()>,
+{
+    if !compression.codec.is_container() && entries.len() != 1 {
+        return Err(WeirdCase { message: format!("A {} file holds a single split, got {}", compression.codec.name(), entries.len()) });
+    }
+    let file = BufWriter::new(fs::File::create(path)?);
+    let level = compression.level.unwrap_or_default();
+    match compression.codec {
+        Codec::None => {
+            let mut writer = file;
+            write_entry(0, &mut writer)?;
+            writer.flush()?;
+        }
+        Codec::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::new(level as u32));
+            write_entry(0, &mut encoder)?;
+            encoder.finish()?.flush()?;
+        }
+        Codec::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(file, level)?;
+            write_entry(0, &mut encoder)?;
+            encoder.finish()?.flush()?;
+        }
+        Codec::Lz4 => {
+            let mut encoder = lz4::EncoderBuilder::new().level(level as u32).build(file)?;
+            write_entry(0, &mut encoder)?;
+            let (mut writer, result) = encoder.finish();
+            result?;
+            writer.flush()?;
+        }
+        Codec::Zip | Codec::ZipStored => {
+            let mut zip = zip::ZipWriter::new(file);
+            let method = if compression.codec == Codec::Zip { zip::CompressionMethod::Deflated } else { zip::CompressionMethod::Stored };
+            let options = zip::write::FileOptions::default()
+                .compression_method(method)
+                .compression_level(compression.level)
+                .unix_permissions(0o644)
+                // A fixed timestamp keeps the archives byte-identical across runs
+                .last_modified_time(zip::DateTime::default())
+                // The entry size is unknown until it is written, so ZIP64 records are always written
+                .large_file(true);
+            for (k, entry) in entries.iter().enumerate() {
+                zip.start_file(entry.as_str(), options).map_err(|e| WeirdCase {
+                    message: format!("Failed to start zip entry for {}: {}", entry, e)
+                })?;
+                write_entry(k, &mut zip)?;
+            }
+            zip.finish().map_err(|e| WeirdCase { message: format!("Failed to finish zip file {}: {}", path, e) })?.flush()?;
+        }
+        Codec::Tar => {
+            let mut writer = file;
+            for (k, entry) in entries.iter().enumerate() {
+                // The header is written again with the entry size once the entry is written
+                let header_position = writer.stream_position()?;
+                writer.write_all(tar_header(entry, 0)?.as_bytes())?;
+                write_entry(k, &mut writer)?;
+                let end = writer.stream_position()?;
+                let size = end - header_position - 512;
+                writer.write_all(&vec![0; tar_padding(size)])?;
+                writer.seek(SeekFrom::Start(header_position))?;
+                writer.write_all(tar_header(entry, size)?.as_bytes())?;
+                writer.seek(SeekFrom::End(0))?;
+            }
+            // End of archive: two empty blocks
+            writer.write_all(&[0; 1024])?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// GNU header of a tar entry, which allows entries over 8 GiB. The timestamp is fixed like for ZIP entries.
+fn tar_header(entry: &str, size: u64) -> io::Result<tar::Header> {
+    let mut header = tar::Header::new_gnu();
+    header.set_path(entry)?;
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+    Ok(header)
+}
+
+/// Zeros completing an entry of `size` bytes to a 512-byte block
+fn tar_padding(size: u64) -> usize {
+    ((512 - size % 512) % 512) as usize
+}
+
+/// Reads the splits of the file at `path` in file order, calling `read_entry` with each split's name and
+/// reader. The single split of a file that is not a container is named `entry`.
+pub fn read_file<F>(path: &Path, codec: Codec, entry: &str, mut read_entry: F) -> DataGenResult<()>
+where
+    F: FnMut(&str, &mut dyn Read) -> DataGenResult<()>,
+{
+    let mut file = BufReader::new(fs::File::open(path)?);
+    match codec {
+        Codec::None => read_entry(entry, &mut file),
+        Codec::Gzip => read_entry(entry, &mut flate2::read::GzDecoder::new(file)),
+        Codec::Zstd => read_entry(entry, &mut zstd::stream::read::Decoder::with_buffer(file)?),
+        Codec::Lz4 => read_entry(entry, &mut lz4::Decoder::new(file)?),
+        Codec::Zip | Codec::ZipStored => {
+            let zip_error = |e: zip::result::ZipError| WeirdCase { message: format!("Failed to read zip file {}: {}", path.display(), e) };
+            let mut zip = zip::ZipArchive::new(file).map_err(zip_error)?;
+            for i in 0..zip.len() {
+                let mut zip_entry = zip.by_index(i).map_err(zip_error)?;
+                let name = zip_entry.name().to_string();
+                read_entry(&name, &mut zip_entry)?;
+            }
+            Ok(())
+        }
+        Codec::Tar => {
+            let mut archive = tar::Archive::new(file);
+            for tar_entry in archive.entries()? {
+                let mut tar_entry = tar_entry?;
+                let name = tar_entry.path()?.to_string_lossy().into_owned();
+                read_entry(&name, &mut tar_entry)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_back_every_codec() {
+        let output_dir = "./output_data/compression_test";
+        fs::create_dir_all(output_dir).unwrap();
+        // Sizes around the tar block size, so the padding gets exercised
+        let contents = [vec![b'a'; 512], vec![b'b'; 700], vec![]];
+        for codec in &[Codec::None, Codec::Gzip, Codec::Zstd, Codec::Lz4, Codec::Zip, Codec::ZipStored, Codec::Tar] {
+            let compression = Compression::new(*codec, None).unwrap();
+            let split_count = if codec.is_container() { contents.len() } else { 1 };
+            let entries: Vec<String> = (0..split_count).map(|k| format!("split_{}.csv", k)).collect();
+            let path = format!("{}/splits.csv{}", output_dir, codec.suffix());
+            write_file(&path, &compression, &entries, |k, writer| {
+                writer.write_all(&contents[k])?;
+                Ok(())
+            }).unwrap();
+
+            let mut read = Vec::new();
+            read_file(Path::new(&path), *codec, &entries[0], |name, reader| {
+                let mut content = Vec::new();
+                reader.read_to_end(&mut content)?;
+                read.push((name.to_string(), content));
+                Ok(())
+            }).unwrap();
+            let expected: Vec<(String, Vec<u8>)> = entries.iter().cloned().zip(contents.iter().cloned()).collect();
+            assert_eq!(read, expected, "{}", codec.name());
+            let _ = fs::remove_file(&path);
+        }
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn check_levels_against_the_codec() {
+        assert_eq!(Compression::new("zstd".parse().unwrap(), None).unwrap(), Compression { codec: Codec::Zstd, level: Some(3) });
+        assert_eq!(Compression::new(Codec::Zip, None).unwrap(), Compression::default());
+        assert_eq!(Compression::new(Codec::Gzip, Some(1)).unwrap().to_string(), "gzip level 1");
+        assert!(Compression::new(Codec::Gzip, Some(10)).is_err());
+        assert!(Compression::new(Codec::Tar, Some(1)).is_err());
+        assert!("brotli".parse::<Codec>().is_err());
+    }
+}
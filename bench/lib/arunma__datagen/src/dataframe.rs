@@ -1,95 +1,66 @@
 use polars::prelude::*;
 use rayon::prelude::*;
-use crate::schema::{Schema, Column};
-use crate::fakegen;
-use crate::{DType, DValue};
+use crate::errors::{DataGenError, DataGenResult};
+use crate::schema::{Column, Schema};
+use crate::fakegen::{self, ColumnGenerator, RecordGenerator};
+use crate::sinks::ColumnValues;
 
-pub fn generate_dataframe(schema: Schema, num_rows: usize) -> Result<DataFrame, PolarsError> {
-    let columns: Vec<Series> = schema.dataset.columns
+pub fn generate_dataframe(mut schema: Schema, num_rows: usize) -> DataGenResult<DataFrame> {
+    let seed = schema.seed.unwrap_or_else(rand::random);
+    schema.resolve_seeds(seed);
+    generate_dataframe_rows(&RecordGenerator::new(&schema)?, 0, num_rows)
+}
+
+/// Generates the `num_rows` rows starting at row index `first_row`, e.g. one row group of a split.
+/// The generator is compiled once from the schema with resolved seeds, so that every batch draws from the same seed.
+pub fn generate_dataframe_rows(generator: &RecordGenerator, first_row: i64, num_rows: usize) -> DataGenResult<DataFrame> {
+    let columns = generator.columns
         .par_iter()
         .map(|col| {
-            generate_series(col, num_rows)
+            generate_series(col, first_row, num_rows)
         })
-        .collect();
-    
-    DataFrame::new(columns)
+        .collect::<DataGenResult<Vec<Series>>>()?;
+
+    Ok(DataFrame::new(columns)?)
 }
 
-fn generate_series(col: &Column, num_rows: usize) -> Series {
-    match col.dtype {
-        DType::Boolean => {
-            let data: Vec<bool> = (0..num_rows)
-                .into_par_iter()
-                .map(|_| {
-                    if let DValue::Boolean(v) = fakegen::generate_fake_data(col.clone()) { v } else { false }
-                })
-                .collect();
-            Series::new(&col.name, data)
-        },
-        DType::Int | DType::Age => {
-            let data: Vec<i32> = (0..num_rows)
-                .into_par_iter()
-                .map(|_| {
-                    if let DValue::Int(v) = fakegen::generate_fake_data(col.clone()) { v } else { 0 }
-                })
-                .collect();
-            Series::new(&col.name, data)
+/// Generates the column's values the way `RecordGenerator::generate_batch` does, NULL values becoming
+/// nulls of the series
+fn generate_series(generator: &ColumnGenerator, first_row: i64, num_rows: usize) -> DataGenResult<Series> {
+    let col = &generator.column;
+    let series = match generator.generate_column(first_row, num_rows)? {
+        ColumnValues::Boolean(data) => Series::new(&col.name, data),
+        ColumnValues::Int(data) => Series::new(&col.name, data),
+        ColumnValues::Long(data) => Series::new(&col.name, data),
+        ColumnValues::Float(data) => Series::new(&col.name, data),
+        ColumnValues::Double(data) => Series::new(&col.name, data),
+        ColumnValues::Str(data) => Series::new(&col.name, data),
+        ColumnValues::Bytes(data) => Series::new(&col.name, data),
+        ColumnValues::Date(data) => {
+            // Days since the epoch, parsed back with the column format
+            let data: Int32Chunked = parse_values(col, data, "date", fakegen::date_to_days)?.into_iter().collect();
+            data.with_name(&col.name).into_date().into_series()
         },
-        DType::Long => {
-            let data: Vec<i64> = (0..num_rows)
-                .into_par_iter()
-                .map(|_| {
-                    if let DValue::Long(v) = fakegen::generate_fake_data(col.clone()) { v } else { 0 }
-                })
-                .collect();
-            Series::new(&col.name, data)
-        },
-        DType::Float => {
-            let data: Vec<f32> = (0..num_rows)
-                .into_par_iter()
-                .map(|_| {
-                    if let DValue::Float(v) = fakegen::generate_fake_data(col.clone()) { v } else { 0.0 }
-                })
-                .collect();
-            Series::new(&col.name, data)
-        },
-        DType::Double => {
-            let data: Vec<f64> = (0..num_rows)
-                .into_par_iter()
-                .map(|_| {
-                    if let DValue::Double(v) = fakegen::generate_fake_data(col.clone()) { v } else { 0.0 }
-                })
-                .collect();
-            Series::new(&col.name, data)
+        ColumnValues::DateTime(data) => {
+            // Milliseconds since the epoch, parsed back with the column format
+            let data: Int64Chunked = parse_values(col, data, "datetime", fakegen::datetime_to_millis)?.into_iter().collect();
+            data.with_name(&col.name).into_datetime(TimeUnit::Milliseconds, None).into_series()
         },
-        DType::String | DType::Name | DType::City | DType::Phone | DType::Latitude | DType::Longitude => {
-            let data: Vec<String> = (0..num_rows)
-                .into_par_iter()
-                .map(|_| {
-                    if let DValue::Str(v) = fakegen::generate_fake_data(col.clone()) { v } else { String::new() }
-                })
-                .collect();
+        ColumnValues::IdSet(data) => {
+            let data: Vec<Option<Series>> = data.into_par_iter().map(|ids| ids.map(|ids| Series::new("", ids))).collect();
             Series::new(&col.name, data)
         },
-        DType::Date => {
-            let data: Vec<String> = (0..num_rows)
-                .into_par_iter()
-                .map(|_| {
-                    if let DValue::Date(v) = fakegen::generate_fake_data(col.clone()) { v } else { String::new() }
-                })
-                .collect();
-            Series::new(&col.name, data)
-        },
-        DType::DateTime => {
-            let data: Vec<String> = (0..num_rows)
-                .into_par_iter()
-                .map(|_| {
-                    if let DValue::DateTime(v) = fakegen::generate_fake_data(col.clone()) { v } else { String::new() }
-                })
-                .collect();
-            Series::new(&col.name, data)
-        },
-    }
+    };
+    Ok(series)
+}
+
+/// Parses the generated dates or datetimes back with the column format
+fn parse_values<T: Send>(col: &Column, data: Vec<Option<String>>, kind: &str, parse: fn(&Column, &str) -> Option<T>) -> DataGenResult<Vec<Option<T>>> {
+    data.into_par_iter()
+        .map(|value| value.map(|value| parse(col, &value).ok_or_else(|| DataGenError::WeirdCase {
+            message: format!("Column {} has {} {} not matching its format", col.name, kind, value)
+        })).transpose())
+        .collect()
 }
 
 #[cfg(test)]
@@ -104,5 +75,19 @@ mod tests {
         println!("{:?}", df);
         assert_eq!(df.height(), 100);
         assert_eq!(df.width(), 6); // id, name, age, adult, gender, date (from schema_simple.yaml based on previous logs)
+        assert_eq!(df.column("date").unwrap().dtype(), &DataType::Date);
+    }
+
+    #[test]
+    fn test_generate_dataframe_with_nulls() {
+        let mut schema = Schema::from_path("./test_data/schema_simple.yaml".to_string()).unwrap();
+        schema.seed = Some(11);
+        for column in schema.dataset.columns.iter_mut() {
+            column.null_ratio = Some(0.5);
+        }
+        let df = generate_dataframe(schema, 200).unwrap();
+        for series in df.get_columns() {
+            assert!((60..140).contains(&series.null_count()), "{} nulls in {}", series.null_count(), series.name());
+        }
     }
 }
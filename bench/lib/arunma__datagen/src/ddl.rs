@@ -0,0 +1,125 @@
+use crate::schema::{Column, Schema};
+use crate::sinks::sql_sink::{dvalue_to_sql, quote_identifier};
+use crate::DType;
+
+/// PostgreSQL type of the values generated for a column, matching what the CSV, COPY and SQL sinks emit
+#[rustfmt::skip]
+pub fn sql_type(dtype: &DType) -> &'static str {
+    match dtype {
+        DType::Boolean          => "boolean",
+        DType::Int              => "integer",
+        DType::Age              => "integer",
+        DType::Long             => "bigint",
+        DType::Serial           => "bigint",
+        DType::Float            => "real",
+        DType::Double           => "double precision",
+        DType::String           => "text",
+        DType::Name             => "text",
+        DType::City             => "text",
+        DType::Phone            => "text",
+        DType::Latitude         => "text",
+        DType::Longitude        => "text",
+        DType::Date             => "date",
+        DType::DateTime         => "timestamp",
+        DType::RoaringBitmap    => "roaringbitmap",
+        DType::RoaringBitmap64  => "roaringbitmap64",
+    }
+}
+
+/// `CREATE TABLE` statement of a schema's dataset. With `keys`, columns marked `primary_key` make up the
+/// table's primary key and every column marked `index` gets a `CREATE INDEX` statement after the table.
+pub fn create_table(schema: &Schema, keys: bool) -> String {
+    let table = quote_identifier(&schema.dataset.name);
+    let mut definitions: Vec<String> = schema.dataset.columns.iter().map(column_definition).collect();
+    let marked = |flag: fn(&Column) -> Option<bool>| -> Vec<&Column> {
+        schema.dataset.columns.iter().filter(|column| keys && flag(column) == Some(true)).collect()
+    };
+    let primary_key = marked(|column| column.primary_key);
+    if !primary_key.is_empty() {
+        let names: Vec<String> = primary_key.iter().map(|column| quote_identifier(&column.name)).collect();
+        definitions.push(format!("PRIMARY KEY ({})", names.join(", ")));
+    }
+
+    let mut ddl = format!("CREATE TABLE {} (\n    {}\n);\n", table, definitions.join(",\n    "));
+    for column in marked(|column| column.index) {
+        let index = quote_identifier(&format!("{}_{}_idx", schema.dataset.name, column.name));
+        ddl.push_str(&format!("CREATE INDEX {} ON {} ({});\n", index, table, quote_identifier(&column.name)));
+    }
+    ddl
+}
+
+/// DDL script of several datasets, in the given order, preceded by the `roaringbitmap` extension
+/// when any of them has a bitmap column
+pub fn create_tables(schemas: &[Schema], keys: bool) -> String {
+    let mut statements: Vec<String> = schemas.iter().map(|schema| create_table(schema, keys)).collect();
+    let has_bitmap = schemas.iter()
+        .any(|schema| schema.dataset.columns.iter().any(|column| column.dtype.is_bitmap()));
+    if has_bitmap {
+        statements.insert(0, "CREATE EXTENSION IF NOT EXISTS roaringbitmap;\n".to_string());
+    }
+    statements.join("\n")
+}
+
+fn column_definition(column: &Column) -> String {
+    let mut definition = format!("{} {}", quote_identifier(&column.name), sql_type(&column.dtype));
+    if column.not_null == Some(true) {
+        definition.push_str(" NOT NULL");
+    }
+    // Bitmap defaults have no literal in every encoding, so they are left out
+    if let (false, Ok(Some(default))) = (column.dtype.is_bitmap(), column.default_value()) {
+        if let Ok(literal) = dvalue_to_sql(column, default) {
+            definition.push_str(&format!(" DEFAULT {}", literal));
+        }
+    }
+    definition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_table_with_keys_and_indexes() {
+        let schema = Schema::from(r#"name: ddl_schema
+dataset:
+  name: books
+  columns:
+    - {name: id, not_null: true, dtype: serial, primary_key: true}
+    - {name: title, dtype: name}
+    - {name: "page count", dtype: int}
+    - {name: price, dtype: double}
+    - {name: out_of_print, dtype: boolean, default: 'false'}
+    - {name: author_id, dtype: long, index: true}
+    - {name: published_on, dtype: date, default: '01/02/2020', format: '%d/%m/%Y'}
+    - {name: created_at, not_null: true, dtype: datetime}
+"#).unwrap();
+        pretty_assertions::assert_eq!(
+            r#"CREATE TABLE "books" (
+    "id" bigint NOT NULL,
+    "title" text,
+    "page count" integer,
+    "price" double precision,
+    "out_of_print" boolean DEFAULT FALSE,
+    "author_id" bigint,
+    "published_on" date DEFAULT '2020-02-01',
+    "created_at" timestamp NOT NULL,
+    PRIMARY KEY ("id")
+);
+CREATE INDEX "books_author_id_idx" ON "books" ("author_id");
+"#,
+            create_table(&schema, true)
+        );
+        assert!(!create_table(&schema, false).contains("PRIMARY KEY"));
+        assert!(!create_table(&schema, false).contains("CREATE INDEX"));
+    }
+
+    #[test]
+    fn create_tables_of_multi_schema_with_bitmaps_and_join_table() {
+        let ddl = crate::schema_ddl("./test_data/schema_relationship.yaml".to_string(), true).unwrap();
+
+        assert!(ddl.starts_with("CREATE EXTENSION IF NOT EXISTS roaringbitmap;\n\nCREATE TABLE \"books\""));
+        assert!(ddl.contains("\"order_ids\" roaringbitmap64\n"));
+        assert!(ddl.contains("CREATE TABLE \"books_orders\" (\n    \"book_id\" bigint NOT NULL,\n    \"order_id\" bigint NOT NULL,\n    PRIMARY KEY (\"book_id\", \"order_id\")\n);\n"));
+        assert_eq!(ddl.matches("CREATE TABLE").count(), 4);
+    }
+}
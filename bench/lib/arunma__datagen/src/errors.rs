@@ -1,10 +1,13 @@
+// The `Fail` derive expands to impls nested in an anonymous const
+#![allow(non_local_definitions)]
+
 use std::{io, result};
 
 pub type DataGenResult<T> = result::Result<T, DataGenError>;
 
 #[derive(Fail, Debug)]
 pub enum DataGenError {
-    #[fail(display = "File IO Error")]
+    #[fail(display = "File IO Error: {}", _0)]
     FileIO(#[cause] io::Error),
 
     #[fail(display = "CSV error")]
@@ -13,6 +16,9 @@ pub enum DataGenError {
     #[fail(display = "SerDe error")]
     SerDe(#[cause] serde_yaml::Error),
 
+    #[fail(display = "Polars error: {}", _0)]
+    Polars(#[cause] polars::prelude::PolarsError),
+
     #[fail(display = "{}", message)]
     WeirdCase { message: String },
 }
@@ -30,3 +36,4 @@ macro_rules! from_error {
 from_error!(csv::Error, DataGenError::Csv);
 from_error!(serde_yaml::Error, DataGenError::SerDe);
 from_error!(io::Error, DataGenError::FileIO);
+from_error!(polars::prelude::PolarsError, DataGenError::Polars);
@@ -4,153 +4,649 @@ use fake::faker::chrono::en::*;
 use fake::faker::lorem::en::*;
 use fake::faker::name::en::*;
 use fake::faker::phone_number::en::*;
-use fake::locales::EN;
-use rand::Rng;
-use rand_distr::{Distribution, Normal, Uniform};
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use rand_pcg::Pcg64Mcg;
+use rayon::prelude::*;
+use roaring::{RoaringBitmap, RoaringTreemap};
 use chrono::prelude::*;
+use chrono::format::{Item, StrftimeItems};
 
 use crate::{DType, DValue};
+use crate::errors::{DataGenError, DataGenResult};
 use crate::fake::Fake;
-use crate::schema::{Column, Schema};
+use crate::schema::{BitmapEncoding, Cardinality, Column, KeySpace, Relationship, Schema};
+use crate::sinks::{Batch, ColumnValues};
 
 // DateTime specifiers https://docs.rs/chrono/0.4.9/chrono/format/strftime/index.html#specifiers
 
-pub fn gen_record_for_schema(schema: Schema) -> DValue {
-    DValue::Record(
-        schema
-            .dataset
-            .columns
-            .into_iter()
-            .map(|col| (col.name.clone(), generate_fake_data(col)))
-            .collect(),
-    )
-}
-
-pub fn generate_fake_data(column: Column) -> DValue {
-    match column.dtype {
-        DType::Boolean => generate_value(column),
-        DType::Int => generate_value(column),
-        DType::Long => generate_value(column),
-        DType::Float => generate_value(column),
-        DType::Double => generate_value(column),
-        DType::String => generate_value(column),
-        DType::Date => generate_value(column),
-        DType::DateTime => generate_value(column),
-
-        //Special types
-        DType::Age => DValue::Int((1..100).fake()),
-        DType::Name => DValue::Str(Name().fake()),
-        DType::City => DValue::Str(CityName().fake()),
-        DType::Phone => DValue::Str(PhoneNumber().fake()),
-
-        DType::Latitude => DValue::Str(Latitude().fake()),
-        DType::Longitude => DValue::Str(Longitude().fake()),
-    }
-}
-
-
-fn generate_value(column: Column) -> DValue {
-    let mut rng = rand::thread_rng();
-    //println!("{:?} column : ", column);
-    if column.one_of.is_some() {
-        let str_val = {
-            let one_off = column.one_of.unwrap();
-            let count = one_off.len();
-            one_off[rng.gen_range(0, count)].clone()
-        };
+/// Generates one record for the schema. `row_index` is the zero-based position of the record
+/// within the whole dataset (not the split) and drives the `serial` columns and the seeded draws.
+/// Compiles the schema for this one record, see `RecordGenerator` to generate many.
+pub fn gen_record_for_schema(schema: Schema, row_index: i64) -> DataGenResult<DValue> {
+    Ok(RecordGenerator::new(&schema)?.generate(row_index))
+}
 
-        //println!("Attempting to cast one_of value {} to datatype {:?}", str_val, column.dtype);
+/// Generates one value of the column, compiling the column for this one value, see `ColumnGenerator`
+/// to generate many.
+pub fn generate_fake_data(column: Column, row_index: i64) -> DataGenResult<DValue> {
+    Ok(ColumnGenerator::new(&column)?.generate(row_index))
+}
 
-        //TODO The following ought to be closed in a Result
-        match column.dtype {
-            DType::Boolean => DValue::Boolean(str_val.parse::<bool>().unwrap()),
-            DType::Int => DValue::Int(str_val.parse::<i32>().unwrap()),
-            DType::Long => DValue::Long(str_val.parse::<i64>().unwrap()),
-            DType::Float => DValue::Float(str_val.parse::<f32>().unwrap()),
-            DType::Double => DValue::Double(str_val.parse::<f64>().unwrap()),
-            DType::DateTime => DValue::Date(str_val),
-            DType::String => DValue::Str(str_val),
-            _ => panic!("ERROR: Unable to parse provided one_off value {} to the expected data type {:?}", str_val, column.dtype)
-        }
-    } else if column.min.is_some() && column.max.is_some() &&
-        (column.dtype == DType::Int || column.dtype == DType::Long || column.dtype == DType::Float || column.dtype == DType::Double) {
-        let from: f64 = column.min.unwrap().parse().unwrap();
-        let to: f64 = column.max.unwrap().parse().unwrap();
-        //println!("{:?} column : ", column);
-        match column.dtype {
-            DType::Int => DValue::Int(rng.gen_range(from, to) as i32),
-            DType::Long => DValue::Long(rng.gen_range(from, to) as i64),
-            DType::Float => DValue::Float(rng.gen_range(from, to) as f32),
-            DType::Double => DValue::Double(rng.gen_range(from, to)),
-            _ => panic!("ERROR: Unable to apply range_from and range_to option to the Datatype {:?}", column.dtype)
-        }
-    } else if column.mean.is_some() && column.std.is_some() {
-        let distribution = Normal::new(column.mean.unwrap(), column.std.unwrap()).unwrap();
-        match column.dtype {
-            DType::Int => DValue::Int(distribution.sample(&mut rng) as i32),
-            DType::Long => DValue::Long(distribution.sample(&mut rng) as i64),
-            DType::Float => DValue::Float(distribution.sample(&mut rng) as f32),
-            DType::Double => DValue::Double(distribution.sample(&mut rng)),
-            _ => panic!("ERROR: Unable to apply mean and std options to the Datatype {:?}", column.dtype)
+/// The columns of a schema compiled into generators. Compiled once per dataset and shared by
+/// reference across the threads generating its rows.
+pub struct RecordGenerator {
+    pub columns: Vec<ColumnGenerator>,
+}
+
+impl RecordGenerator {
+    pub fn new(schema: &Schema) -> DataGenResult<RecordGenerator> {
+        let columns = schema.dataset.columns
+            .iter()
+            .map(ColumnGenerator::new)
+            .collect::<DataGenResult<Vec<ColumnGenerator>>>()?;
+        Ok(RecordGenerator { columns })
+    }
+
+    /// Generates the record at `row_index`, see `gen_record_for_schema`
+    pub fn generate(&self, row_index: i64) -> DValue {
+        DValue::Record(
+            self.columns
+                .iter()
+                .map(|generator| (generator.column.name.clone(), generator.generate(row_index)))
+                .collect(),
+        )
+    }
+
+    /// Generates the `num_rows` records from row index `first_row` column by column, in parallel
+    pub fn generate_batch(&self, first_row: i64, num_rows: usize) -> DataGenResult<Batch> {
+        Ok(Batch {
+            names: self.columns.iter().map(|generator| generator.column.name.clone()).collect(),
+            columns: self.columns.par_iter().map(|generator| generator.generate_column(first_row, num_rows)).collect::<DataGenResult<_>>()?,
+            num_rows,
+        })
+    }
+}
+
+/// A column compiled into a generator: the default, the `one_of` values, the bounds, the normal
+/// distributions and the date format are parsed once, rather than for every value.
+///
+/// Every value is drawn from its own generator, seeded by the column seed and the row index, so
+/// the output only depends on the seed and never on how rows are scheduled across threads.
+/// Columns without a resolved seed (see `Schema::resolve_seeds`) get a random one.
+/// A `null_ratio` share of the rows hold NULL and a `default_ratio` share hold the column default,
+/// decided by a generator of their own, so the other rows keep the values they would have without them.
+pub struct ColumnGenerator {
+    /// The compiled column, e.g. for its name and format
+    pub column: Column,
+    seed: u64,
+    share_seed: u64,
+    null_ratio: f64,
+    default_ratio: f64,
+    default: Option<DValue>,
+    values: Values,
+}
+
+/// How a column draws its values, in the order the column options take precedence
+enum Values {
+    /// Sorted, distinct ids of a bitmap column, encoded as the portable Roaring format when `binary`
+    IdSet { id_space: KeySpace, cardinality: Count, binary: bool },
+    /// Foreign keys drawn uniformly from the key space of the referenced serial column
+    Reference(KeySpace),
+    /// Sequential primary keys: `min` is the first id (1 by default) and every row gets `min + row_index`,
+    /// so ids are unique and gapless across all file splits
+    Serial(i64),
+    OneOf(Vec<DValue>),
+    /// Numbers drawn uniformly from `min..max`
+    Uniform(f64, f64),
+    Normal(Normal<f64>),
+    /// Dates and datetimes, drawn from `min`..`max` when both are set, written with the column format
+    Temporal { format: Vec<Item<'static>>, range: Option<(chrono::DateTime<Utc>, chrono::DateTime<Utc>)> },
+    /// Values of the fake-rs generator of the dtype
+    Fake,
+}
+
+/// A bitmap cardinality with its normal distribution built once
+enum Count {
+    Fixed(usize),
+    Range(usize, usize),
+    Normal(Normal<f64>),
+}
+
+impl ColumnGenerator {
+    pub fn new(column: &Column) -> DataGenResult<ColumnGenerator> {
+        let invalid = |problem: String| DataGenError::WeirdCase { message: format!("Column {} {}", column.name, problem) };
+        let seed = column.seed.unwrap_or_else(rand::random);
+        let default_ratio = column.default_ratio.unwrap_or_default();
+        let default = column.default_value()
+            .map_err(|reason| invalid(format!("has an invalid default {:?}: {}", column.default.as_deref().unwrap_or_default(), reason)))?;
+        if default.is_none() && default_ratio > 0.0 {
+            return Err(invalid("has a default_ratio but no default".to_string()));
         }
-    } else {
-        match column.dtype {
-            DType::Boolean => DValue::Boolean(Faker.fake::<bool>()),
-            DType::Int => DValue::Int(Faker.fake::<i32>()),
-            DType::Long => DValue::Long(Faker.fake::<i64>()),
-            DType::Float => DValue::Float(Faker.fake::<f32>()),
-            DType::Double => DValue::Double(Faker.fake::<f64>()),
-            DType::Date => {
-                if column.format.is_none() {
-                    panic!("Format is a mandatory parameter for date datatype");
-                }
-                let date_fmt = column.format.unwrap();
-                let rnd_date: chrono::DateTime<Utc> = {
-                    if column.min.is_some() && column.max.is_some() {
-                        let start_dt_naive = NaiveDate::parse_from_str(column.min.unwrap().as_str(), date_fmt.as_str()).unwrap();
-                        let start_dt: chrono::DateTime<Utc> = DateTime::from_utc(NaiveDateTime::new(start_dt_naive, chrono::NaiveTime::from_hms(0, 0, 0)), Utc);
-                        let end_dt_naive = NaiveDate::parse_from_str(column.max.unwrap().as_str(), date_fmt.as_str()).unwrap();
-                        let end_dt: chrono::DateTime<Utc> = DateTime::from_utc(NaiveDateTime::new(end_dt_naive, chrono::NaiveTime::from_hms(0, 0, 0)), Utc);
-                        DateTimeBetween(start_dt, end_dt).fake()
-                    } else {
-                        DateTime().fake()
-                    }
-                };
-                DValue::Date(rnd_date.format(date_fmt.as_str()).to_string())
+        Ok(ColumnGenerator {
+            column: column.clone(),
+            seed,
+            share_seed: derive_seed(seed, "share"),
+            null_ratio: column.null_ratio.unwrap_or_default(),
+            default_ratio,
+            default,
+            values: Values::new(column).map_err(invalid)?,
+        })
+    }
+
+    pub fn generate(&self, row_index: i64) -> DValue {
+        if self.null_ratio > 0.0 || self.default_ratio > 0.0 {
+            let share: f64 = cell_rng(self.share_seed, row_index).gen();
+            if share < self.null_ratio {
+                return DValue::Null;
             }
-            DType::DateTime => {
-                if column.format.is_none() {
-                    panic!("Format is a mandatory parameter for date datatype");
+            if let (true, Some(default)) = (share < self.null_ratio + self.default_ratio, &self.default) {
+                return default.clone();
+            }
+        }
+        let rng = &mut cell_rng(self.seed, row_index);
+        let dtype = &self.column.dtype;
+        match &self.values {
+            Values::IdSet { id_space, cardinality, binary } => {
+                let ids = sample_ids(*id_space, cardinality, rng);
+                if *binary { DValue::Bytes(serialize_bitmap(dtype, &ids)) } else { DValue::IdSet(ids) }
+            }
+            Values::Reference(key_space) => {
+                let key = rng.gen_range(key_space.start..key_space.start + key_space.count);
+                match dtype {
+                    DType::Int => DValue::Int(key as i32),
+                    _ => DValue::Long(key),
                 }
-                let date_fmt = column.format.unwrap();
-                let rnd_date: chrono::DateTime<Utc> = {
-                    if column.min.is_some() && column.max.is_some() {
-                        let start_dt = Utc.datetime_from_str(column.min.unwrap().as_str(), date_fmt.as_str()).unwrap();
-                        let end_dt = Utc.datetime_from_str(column.max.unwrap().as_str(), date_fmt.as_str()).unwrap();
-                        DateTimeBetween(start_dt, end_dt).fake()
-                    } else {
-                        DateTime().fake()
-                    }
+            }
+            Values::Serial(start) => DValue::Long(start + row_index),
+            Values::OneOf(values) => values[rng.gen_range(0..values.len())].clone(),
+            Values::Uniform(from, to) => number(dtype, rng.gen_range(*from..*to)),
+            Values::Normal(distribution) => number(dtype, distribution.sample(rng)),
+            Values::Temporal { format, range } => {
+                let value: chrono::DateTime<Utc> = match range {
+                    Some((start, end)) => DateTimeBetween(*start, *end).fake_with_rng(rng),
+                    None => DateTime().fake_with_rng(rng),
                 };
-                DValue::DateTime(rnd_date.format(date_fmt.as_str()).to_string())
+                let text = value.format_with_items(format.iter()).to_string();
+                if *dtype == DType::Date { DValue::Date(text) } else { DValue::DateTime(text) }
             }
-            DType::String => DValue::Str(Word().fake()),
-            _ => panic!("Error : The current version does not support one_of for this datatype {:?}", column.dtype)
+            Values::Fake => match dtype {
+                DType::Boolean => DValue::Boolean(Faker.fake_with_rng::<bool, _>(rng)),
+                DType::Int => DValue::Int(Faker.fake_with_rng::<i32, _>(rng)),
+                DType::Long => DValue::Long(Faker.fake_with_rng::<i64, _>(rng)),
+                DType::Float => DValue::Float(Faker.fake_with_rng::<f32, _>(rng)),
+                DType::Double => DValue::Double(Faker.fake_with_rng::<f64, _>(rng)),
+                DType::String => DValue::Str(Word().fake_with_rng(rng)),
+
+                //Special types
+                DType::Age => DValue::Int((1..100).fake_with_rng(rng)),
+                DType::Name => DValue::Str(Name().fake_with_rng(rng)),
+                DType::City => DValue::Str(CityName().fake_with_rng(rng)),
+                DType::Phone => DValue::Str(PhoneNumber().fake_with_rng(rng)),
+
+                DType::Latitude => DValue::Str(Latitude().fake_with_rng(rng)),
+                DType::Longitude => DValue::Str(Longitude().fake_with_rng(rng)),
+                _ => unreachable!("{:?} columns are compiled into other values", dtype),
+            },
+        }
+    }
+
+    /// Generates the column's values of the `num_rows` rows from row index `first_row`, in parallel
+    pub fn generate_column(&self, first_row: i64, num_rows: usize) -> DataGenResult<ColumnValues> {
+        Ok(match (&self.values, &self.column.dtype) {
+            (Values::IdSet { binary: true, .. }, _) => ColumnValues::Bytes(self.generate_values(first_row, num_rows, "bytes", |v| match v { DValue::Bytes(v) => Ok(v), v => Err(v) })?),
+            (Values::IdSet { .. }, _) => ColumnValues::IdSet(self.generate_values(first_row, num_rows, "id set", |v| match v { DValue::IdSet(v) => Ok(v), v => Err(v) })?),
+            (Values::Reference(_), DType::Int) | (_, DType::Int | DType::Age) => ColumnValues::Int(self.generate_values(first_row, num_rows, "int", |v| match v { DValue::Int(v) => Ok(v), v => Err(v) })?),
+            (Values::Reference(_), _) | (_, DType::Long | DType::Serial) => ColumnValues::Long(self.generate_values(first_row, num_rows, "long", |v| match v { DValue::Long(v) => Ok(v), v => Err(v) })?),
+            (_, DType::Boolean) => ColumnValues::Boolean(self.generate_values(first_row, num_rows, "boolean", |v| match v { DValue::Boolean(v) => Ok(v), v => Err(v) })?),
+            (_, DType::Float) => ColumnValues::Float(self.generate_values(first_row, num_rows, "float", |v| match v { DValue::Float(v) => Ok(v), v => Err(v) })?),
+            (_, DType::Double) => ColumnValues::Double(self.generate_values(first_row, num_rows, "double", |v| match v { DValue::Double(v) => Ok(v), v => Err(v) })?),
+            (_, DType::Date) => ColumnValues::Date(self.generate_values(first_row, num_rows, "date", |v| match v { DValue::Date(v) => Ok(v), v => Err(v) })?),
+            // `one_of` datetimes come out as `DValue::Date`
+            (_, DType::DateTime) => ColumnValues::DateTime(self.generate_values(first_row, num_rows, "datetime", |v| match v { DValue::DateTime(v) | DValue::Date(v) => Ok(v), v => Err(v) })?),
+            _ => ColumnValues::Str(self.generate_values(first_row, num_rows, "string", |v| match v { DValue::Str(v) => Ok(v), v => Err(v) })?),
+        })
+    }
+
+    /// Values of the rows, NULL becoming `None`. Fails on a value `extract` does not take, which would
+    /// otherwise pass for NULL.
+    fn generate_values<T: Send>(&self, first_row: i64, num_rows: usize, kind: &str, extract: fn(DValue) -> Result<T, DValue>) -> DataGenResult<Vec<Option<T>>> {
+        (0..num_rows)
+            .into_par_iter()
+            .map(|i| match self.generate(first_row + i as i64) {
+                DValue::Null => Ok(None),
+                value => extract(value).map(Some).map_err(|value| DataGenError::WeirdCase {
+                    message: format!("Column {} generated {:?} among its {} values", self.column.name, value, kind)
+                }),
+            })
+            .collect()
+    }
+}
+
+impl Values {
+    /// Parses the column options, or tells why they cannot generate values. `Schema::validate` reports
+    /// the same problems, along with the ones of options that would be ignored.
+    fn new(column: &Column) -> Result<Values, String> {
+        let numeric = matches!(column.dtype, DType::Int | DType::Long | DType::Float | DType::Double);
+        let bounds = (column.min.as_deref(), column.max.as_deref());
+        if column.dtype.is_bitmap() {
+            let cardinality = column.cardinality.as_ref().ok_or("is a bitmap column without a cardinality")?;
+            return Ok(Values::IdSet {
+                id_space: bitmap_id_space(column)?,
+                cardinality: Count::new(cardinality)?,
+                binary: column.encoding == Some(BitmapEncoding::Binary),
+            });
+        }
+        if let Some(reference) = &column.references {
+            let key_space = column.key_space
+                .ok_or_else(|| format!("references {}, which was not resolved against its parent schema", reference))?;
+            return Ok(Values::Reference(key_space));
+        }
+        match column.dtype {
+            DType::Serial => {
+                let start = bounds.0.map_or(Ok(1), str::parse).map_err(|e| format!("has an invalid first id: {}", e))?;
+                return Ok(Values::Serial(start));
+            }
+            DType::Boolean | DType::Int | DType::Long | DType::Float | DType::Double | DType::String | DType::Date | DType::DateTime => {}
+            _ => return Ok(Values::Fake),
+        }
+        if let Some(one_of) = &column.one_of {
+            if one_of.is_empty() {
+                return Err("has an empty one_of".to_string());
+            }
+            let values = one_of.iter()
+                .map(|value| column.parse_value(value)
+                    // `one_of` datetimes come out as `DValue::Date`
+                    .map(|value| match value { DValue::DateTime(text) => DValue::Date(text), value => value })
+                    .map_err(|reason| format!("has an invalid one_of value {:?}: {}", value, reason)))
+                .collect::<Result<Vec<DValue>, String>>()?;
+            return Ok(Values::OneOf(values));
+        }
+        if let (Some(min), Some(max), true) = (bounds.0, bounds.1, numeric) {
+            let (from, to) = match (min.parse::<f64>(), max.parse::<f64>()) {
+                (Ok(from), Ok(to)) if from < to => (from, to),
+                _ => return Err(format!("has min {:?} and max {:?}, expected numbers with min below max", min, max)),
+            };
+            return Ok(Values::Uniform(from, to));
+        }
+        if let (Some(mean), Some(std)) = (column.mean, column.std) {
+            if !numeric {
+                return Err(format!("has mean and std, which {:?} columns do not take", column.dtype));
+            }
+            return Ok(Values::Normal(Normal::new(mean, std).map_err(|e| format!("has an invalid mean and std: {}", e))?));
+        }
+        if matches!(column.dtype, DType::Date | DType::DateTime) {
+            let format = column.format.as_deref().ok_or("is a date column without a format")?;
+            let items = StrftimeItems::new(format).parse_to_owned().map_err(|e| format!("has an invalid format {:?}: {}", format, e))?;
+            let range = match bounds {
+                (Some(min), Some(max)) => {
+                    let parse = |text: &str| match column.dtype {
+                        DType::Date => parse_date(column, text).map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc()),
+                        _ => parse_datetime(column, text).map(|datetime| datetime.and_utc()),
+                    };
+                    match (parse(min), parse(max)) {
+                        (Some(start), Some(end)) => Some((start, end)),
+                        _ => return Err(format!("has min {:?} and max {:?} not matching its format {:?}", min, max, format)),
+                    }
+                }
+                _ => None,
+            };
+            return Ok(Values::Temporal { format: items, range });
+        }
+        Ok(Values::Fake)
+    }
+}
+
+/// A drawn number as a value of the numeric dtype
+fn number(dtype: &DType, value: f64) -> DValue {
+    match dtype {
+        DType::Int => DValue::Int(value as i32),
+        DType::Long => DValue::Long(value as i64),
+        DType::Float => DValue::Float(value as f32),
+        _ => DValue::Double(value),
+    }
+}
+
+/// Parses a generated `date` value back with the column format
+pub fn parse_date(column: &Column, value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, column.format.as_deref().unwrap_or_default()).ok()
+}
+
+/// Parses a generated `datetime` value back with the column format
+pub fn parse_datetime(column: &Column, value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, column.format.as_deref().unwrap_or_default()).ok()
+}
+
+/// Days since the epoch of a generated `date` value
+pub fn date_to_days(column: &Column, value: &str) -> Option<i32> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    parse_date(column, value).map(|date| (date - epoch).num_days() as i32)
+}
+
+/// Milliseconds since the epoch of a generated `datetime` value
+pub fn datetime_to_millis(column: &Column, value: &str) -> Option<i64> {
+    parse_datetime(column, value).map(|datetime| datetime.and_utc().timestamp_millis())
+}
+
+/// ISO 8601 text of a generated `date` or `datetime` value, whatever the column format. `one_of` datetimes
+/// come out as `DValue::Date`, so the column type decides.
+pub fn iso_date(column: &Column, value: &str) -> DataGenResult<String> {
+    if column.dtype == DType::DateTime {
+        parse_datetime(column, value).map(|datetime| datetime.format("%Y-%m-%d %H:%M:%S%.f").to_string()).ok_or_else(|| DataGenError::WeirdCase {
+            message: format!("Column {} has datetime {} not matching its format", column.name, value)
+        })
+    } else {
+        parse_date(column, value).map(|date| date.format("%Y-%m-%d").to_string()).ok_or_else(|| DataGenError::WeirdCase {
+            message: format!("Column {} has date {} not matching its format", column.name, value)
+        })
+    }
+}
+
+/// Derives a child seed from a seed and a name, e.g. a dataset seed from the schema seed or a
+/// column seed from the dataset seed. Uses FNV-1a so it is stable across runs and platforms.
+pub fn derive_seed(seed: u64, name: &str) -> u64 {
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3));
+    splitmix64(seed ^ hash)
+}
+
+/// Generator of one cell: the column seed and the row index fully determine the values drawn
+pub fn cell_rng(seed: u64, row_index: i64) -> Pcg64Mcg {
+    Pcg64Mcg::seed_from_u64(splitmix64(seed.wrapping_add((row_index as u64).wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15))))
+}
+
+/// SplitMix64 finalizer, so neighbouring seeds get unrelated streams
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Portable Roaring serialization of the ids: the 32-bit format for `roaringbitmap`
+/// and the 64-bit treemap format for `roaringbitmap64`
+pub fn serialize_bitmap(dtype: &DType, ids: &[i64]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match dtype {
+        DType::RoaringBitmap => {
+            let bitmap: RoaringBitmap = ids.iter().map(|id| *id as u32).collect();
+            bitmap.serialize_into(&mut bytes).unwrap();
+        }
+        _ => {
+            let treemap: RoaringTreemap = ids.iter().map(|id| *id as u64).collect();
+            treemap.serialize_into(&mut bytes).unwrap();
+        }
+    }
+    bytes
+}
+
+/// The association draws of a relationship, compiled once per join table and shared by reference
+/// across the threads generating its rows
+pub struct RelationshipGenerator {
+    target: KeySpace,
+    cardinality: Count,
+    seed: u64,
+}
+
+impl RelationshipGenerator {
+    pub fn new(relationship: &Relationship, target: KeySpace, seed: u64) -> DataGenResult<RelationshipGenerator> {
+        let cardinality = Count::new(&relationship.cardinality).map_err(|problem| DataGenError::WeirdCase {
+            message: format!("Relationship {} {}", relationship.name, problem)
+        })?;
+        Ok(RelationshipGenerator { target, cardinality, seed })
+    }
+
+    /// Target keys the relationship links to one owner row. Relationship bitmap columns carry the
+    /// relationship seed, so they draw the very same ids for the owner row as the join table.
+    pub fn ids(&self, owner_row: i64) -> Vec<i64> {
+        sample_ids(self.target, &self.cardinality, &mut cell_rng(self.seed, owner_row))
+    }
+}
+
+fn sample_ids<R: Rng>(id_space: KeySpace, cardinality: &Count, rng: &mut R) -> Vec<i64> {
+    let amount = std::cmp::min(cardinality.sample(rng) as i64, id_space.count) as usize;
+    let mut ids: Vec<i64> = rand::seq::index::sample(rng, id_space.count as usize, amount)
+        .into_iter()
+        .map(|i| id_space.start + i as i64)
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// Ids of a bitmap column: the referenced key space or `min..=max`
+fn bitmap_id_space(column: &Column) -> Result<KeySpace, String> {
+    let id_space = match (column.key_space, &column.min, &column.max) {
+        (Some(key_space), _, _) => key_space,
+        (None, Some(min), Some(max)) => match (min.parse::<i64>(), max.parse::<i64>()) {
+            (Ok(start), Ok(end)) => KeySpace { start, count: end - start + 1 },
+            _ => return Err(format!("has min {:?} and max {:?}, expected integer ids", min, max)),
+        },
+        _ => return Err("needs either references or min and max to define its id space".to_string()),
+    };
+    if id_space.start < 0 {
+        return Err(format!("has id space {:?} with negative ids, which a roaring bitmap cannot hold", id_space));
+    }
+    // Postgres roaringbitmap takes int4 ids, so the 32-bit id space stops at i32::MAX
+    if column.dtype == DType::RoaringBitmap && id_space.start + id_space.count - 1 > i64::from(i32::MAX) {
+        return Err(format!("has id space {:?}, which does not fit in a 32-bit roaringbitmap, use roaringbitmap64", id_space));
+    }
+    Ok(id_space)
+}
+
+impl Count {
+    fn new(cardinality: &Cardinality) -> Result<Count, String> {
+        Ok(match *cardinality {
+            Cardinality::Fixed(count) => Count::Fixed(count),
+            Cardinality::Range { min, max } => Count::Range(min, max),
+            Cardinality::Normal { mean, std } => Count::Normal(Normal::new(mean, std).map_err(|e| format!("has an invalid normal cardinality: {}", e))?),
+        })
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        match self {
+            Count::Fixed(count) => *count,
+            Count::Range(min, max) => rng.gen_range(*min..=*max),
+            Count::Normal(distribution) => distribution.sample(rng).round().max(0.0) as usize,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::fakegen::gen_record_for_schema;
-    use crate::schema::Schema;
+    use crate::fakegen::{gen_record_for_schema, generate_fake_data, serialize_bitmap, ColumnGenerator, RecordGenerator, RelationshipGenerator};
+    use crate::schema::{BitmapEncoding, Cardinality, KeySpace, Relationship, Schema};
+    use crate::{DType, DValue};
 
     #[test]
     fn generate_record_from_schema() {
         let schema = Schema::from_path("./test_data/schema_simple.yaml".to_string()).unwrap();
         //pretty_assertions::assert_eq!(format!("{:?}", schema), r#"Schema { name: "person_schema", dataset: DataSet { name: "person_table", columns: [Column { name: "id", not_null: Some(false), dtype: Int }, Column { name: "name", not_null: None, dtype: Name }, Column { name: "age", not_null: None, dtype: Age }, Column { name: "adult", not_null: None, dtype: Boolean }, Column { name: "gender", not_null: None, dtype: Gender }] } }"#);
-        let record = gen_record_for_schema(schema);
+        let record = gen_record_for_schema(schema, 0).unwrap();
         println!("Record {:?}", record.clone());
-        assert!(bincode::serialize(&record).unwrap().len() > 0);
+        assert!(!bincode::serialize(&record).unwrap().is_empty());
+    }
+
+    #[test]
+    fn generate_record_from_seed_regardless_of_order() {
+        let mut schema = Schema::from_path("./test_data/schema_options.yaml".to_string()).unwrap();
+        schema.resolve_seeds(7);
+        let forward: Vec<_> = (0..50).map(|i| gen_record_for_schema(schema.clone(), i).unwrap()).collect();
+        let mut backward: Vec<_> = (0..50).rev().map(|i| gen_record_for_schema(schema.clone(), i).unwrap()).collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+
+        schema.dataset.columns.iter_mut().for_each(|column| column.seed = None);
+        schema.resolve_seeds(8);
+        let reseeded: Vec<_> = (0..50).map(|i| gen_record_for_schema(schema.clone(), i).unwrap()).collect();
+        assert_ne!(forward, reseeded);
+    }
+
+    #[test]
+    fn compiled_generators_draw_the_same_records() {
+        let mut schema = Schema::from_path("./test_data/schema_options.yaml".to_string()).unwrap();
+        schema.resolve_seeds(7);
+        let generator = RecordGenerator::new(&schema).unwrap();
+        for i in 0..50 {
+            assert_eq!(generator.generate(i), gen_record_for_schema(schema.clone(), i).unwrap());
+        }
+
+        // Batches hold the same values, column by column, NULL values included
+        schema.dataset.columns.iter_mut().for_each(|column| column.null_ratio = Some(0.2));
+        let generator = RecordGenerator::new(&schema).unwrap();
+        let batch = generator.generate_batch(10, 40).unwrap();
+        assert_eq!(batch.num_rows, 40);
+        assert_eq!(batch.into_records().collect::<Vec<DValue>>(), (10..50).map(|i| generator.generate(i)).collect::<Vec<DValue>>());
+
+        // Options that cannot generate values fail when compiling rather than on the first value
+        let mut date = schema.dataset.columns.iter().find(|column| column.dtype == DType::Date).unwrap().clone();
+        date.format = None;
+        assert_eq!(format!("{}", ColumnGenerator::new(&date).err().unwrap()), format!("Column {} is a date column without a format", date.name));
+
+        // A value of another type than the column's fails the batch rather than passing for NULL
+        let mut schema = Schema::from_path("./test_data/schema_simple.yaml".to_string()).unwrap();
+        let mut gender = ColumnGenerator::new(&schema.dataset.columns.remove(4)).unwrap();
+        gender.column.dtype = DType::Int;
+        let err = gender.generate_column(0, 10).err().unwrap();
+        assert!(format!("{}", err).starts_with("Column gender generated Str("), "{}", err);
+    }
+
+    #[test]
+    fn generate_serial_from_row_index() {
+        let schema = Schema::from_path("./test_data/schema_serial.yaml".to_string()).unwrap();
+        let id = schema.dataset.columns[0].clone();
+        assert_eq!(generate_fake_data(id.clone(), 0).unwrap(), DValue::Long(1));
+        assert_eq!(generate_fake_data(id, 41).unwrap(), DValue::Long(42));
+        let order_id = schema.dataset.columns[1].clone();
+        assert_eq!(generate_fake_data(order_id, 0).unwrap(), DValue::Long(1000));
+    }
+
+    #[test]
+    fn generate_nulls_at_the_null_ratio() {
+        let mut schema = Schema::from_path("./test_data/schema_simple.yaml".to_string()).unwrap();
+        schema.resolve_seeds(3);
+        for column in schema.dataset.columns {
+            let values: Vec<DValue> = (0..1000).map(|i| generate_fake_data(column.clone(), i).unwrap()).collect();
+            let nullable = crate::schema::Column { null_ratio: Some(0.25), ..column.clone() };
+            let with_nulls: Vec<DValue> = (0..1000).map(|i| generate_fake_data(nullable.clone(), i).unwrap()).collect();
+            let nulls = with_nulls.iter().filter(|value| **value == DValue::Null).count();
+            assert!((200..300).contains(&nulls), "{} nulls in column {}", nulls, column.name);
+            // The other rows keep their values
+            assert!(values.iter().zip(&with_nulls).all(|(value, with_null)| *with_null == DValue::Null || with_null == value));
+            assert!((0..10).all(|i| generate_fake_data(crate::schema::Column { null_ratio: Some(1.0), ..column.clone() }, i).unwrap() == DValue::Null));
+        }
+    }
+
+    #[test]
+    fn generate_defaults_at_the_default_ratio() {
+        let mut schema = Schema::from_path("./test_data/schema_simple.yaml".to_string()).unwrap();
+        schema.resolve_seeds(3);
+        let adult = crate::schema::Column { default_ratio: Some(0.6), null_ratio: Some(0.2), ..schema.dataset.columns[3].clone() };
+        let values: Vec<DValue> = (0..1000).map(|i| generate_fake_data(adult.clone(), i).unwrap()).collect();
+        let nulls = values.iter().filter(|value| **value == DValue::Null).count();
+        let trues = values.iter().filter(|value| **value == DValue::Boolean(true)).count();
+        assert!((150..250).contains(&nulls), "{} nulls", nulls);
+        // Half of the remaining fifth of the rows draw true
+        assert!((50..150).contains(&trues), "{} true values", trues);
+    }
+
+    #[test]
+    fn generate_reference_within_key_space() {
+        let mut schema = Schema::from_path("./test_data/schema_serial.yaml".to_string()).unwrap();
+        let mut author_id = schema.dataset.columns.remove(0);
+        author_id.dtype = crate::DType::Int;
+        author_id.references = Some("authors.id".to_string());
+        author_id.key_space = Some(KeySpace { start: 5, count: 3 });
+        for _ in 0..100 {
+            match generate_fake_data(author_id.clone(), 0).unwrap() {
+                DValue::Int(key) => assert!((5..8).contains(&key)),
+                other => panic!("Unexpected value {:?}", other),
+            }
+        }
+
+        author_id.key_space = None;
+        let err = generate_fake_data(author_id, 0).unwrap_err();
+        assert_eq!(format!("{}", err), "Column id references authors.id, which was not resolved against its parent schema");
+    }
+
+    #[test]
+    fn generate_distinct_sorted_id_sets() {
+        let mut schema = Schema::from_path("./test_data/schema_serial.yaml".to_string()).unwrap();
+        let mut book_ids = schema.dataset.columns.remove(0);
+        book_ids.dtype = crate::DType::RoaringBitmap64;
+        book_ids.key_space = Some(KeySpace { start: 100, count: 20 });
+        book_ids.cardinality = Some(Cardinality::Range { min: 2, max: 5 });
+        for _ in 0..100 {
+            match generate_fake_data(book_ids.clone(), 0).unwrap() {
+                DValue::IdSet(ids) => {
+                    assert!((2..=5).contains(&ids.len()));
+                    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+                    assert!(ids.iter().all(|id| (100..120).contains(id)));
+                }
+                other => panic!("Unexpected value {:?}", other),
+            }
+        }
+
+        // A cardinality larger than the id space takes every id once
+        book_ids.cardinality = Some(Cardinality::Fixed(50));
+        assert_eq!(generate_fake_data(book_ids, 0).unwrap(), DValue::IdSet((100..120).collect()));
+    }
+
+    #[test]
+    fn serialize_bitmaps_in_portable_format() {
+        // cookie 12346, one container with key 0 and cardinality 3, offset 16, then the values
+        pretty_assertions::assert_eq!(
+            serialize_bitmap(&DType::RoaringBitmap, &[1, 2, 3]),
+            vec![0x3a, 0x30, 0, 0, 1, 0, 0, 0, 0, 0, 2, 0, 16, 0, 0, 0, 1, 0, 2, 0, 3, 0]
+        );
+        // two 32-bit bitmaps, keyed by the high 32 bits of the ids
+        pretty_assertions::assert_eq!(
+            serialize_bitmap(&DType::RoaringBitmap64, &[7, (1 << 32) + 5]),
+            vec![
+                2, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0x3a, 0x30, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 7, 0,
+                1, 0, 0, 0, 0x3a, 0x30, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 5, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_binary_encoded_bitmap() {
+        let mut schema = Schema::from_path("./test_data/schema_serial.yaml".to_string()).unwrap();
+        let mut book_ids = schema.dataset.columns.remove(0);
+        book_ids.dtype = DType::RoaringBitmap;
+        book_ids.key_space = Some(KeySpace { start: 1, count: 3 });
+        book_ids.cardinality = Some(Cardinality::Fixed(3));
+        book_ids.encoding = Some(BitmapEncoding::Binary);
+        assert_eq!(generate_fake_data(book_ids, 0).unwrap(), DValue::Bytes(serialize_bitmap(&DType::RoaringBitmap, &[1, 2, 3])));
+    }
+
+    #[test]
+    fn relationship_ids_are_seeded_per_owner_row() {
+        let target = KeySpace { start: 1, count: 1000 };
+        let cardinality = Cardinality::Range { min: 5, max: 10 };
+        let relationship = Relationship {
+            name: "books_orders".to_string(),
+            owner: "books.id".to_string(),
+            target: "orders.id".to_string(),
+            cardinality: cardinality.clone(),
+            seed: None,
+            join_table: None,
+            line: None,
+        };
+        let generator = RelationshipGenerator::new(&relationship, target, 42).unwrap();
+        assert_eq!(generator.ids(7), generator.ids(7));
+        assert_ne!(generator.ids(7), generator.ids(8));
+        assert_ne!(generator.ids(7), RelationshipGenerator::new(&relationship, target, 43).unwrap().ids(7));
+
+        let normal = Relationship { cardinality: Cardinality::Normal { mean: 3.0, std: f64::INFINITY }, ..relationship.clone() };
+        let err = RelationshipGenerator::new(&normal, target, 42).err().unwrap();
+        assert!(format!("{}", err).starts_with("Relationship books_orders has an invalid normal cardinality"), "{}", err);
+
+        let mut schema = Schema::from_path("./test_data/schema_serial.yaml".to_string()).unwrap();
+        let mut order_ids = schema.dataset.columns.remove(0);
+        order_ids.dtype = DType::RoaringBitmap64;
+        order_ids.key_space = Some(target);
+        order_ids.cardinality = Some(cardinality.clone());
+        order_ids.seed = Some(42);
+        assert_eq!(generate_fake_data(order_ids, 7).unwrap(), DValue::IdSet(generator.ids(7)));
     }
 }
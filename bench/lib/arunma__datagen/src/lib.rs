@@ -9,30 +9,37 @@ extern crate serde_derive;
 extern crate serde_yaml;
 extern crate chrono;
 
-use std::io;
 use std::fs;
-use std::cmp;
+use std::io;
 use std::path::Path;
-use zip::ZipWriter;
-use zip::write::FileOptions;
-use std::io::BufWriter;
-use std::io::Cursor;
 use std::io::Write;
 
 
+use crate::compression::Compression;
 use crate::errors::DataGenResult;
-use crate::schema::Schema;
-use crate::sinks::{csv_sink, Sink};
+use crate::fakegen::{RecordGenerator, RelationshipGenerator};
+use crate::load_script::Archive;
+use crate::manifest::{DataSetManifest, FileManifest, Manifest};
+use crate::resume::ResumeState;
+use crate::schema::{JoinTable, KeySpace, MultiSchema, Relationship, Schema};
+use crate::sinks::{avro_sink, csv_sink, json_sink, pgcopy_sink, sql_sink, Sink};
+use crate::sinks::csv_sink::CsvOptions;
 
+pub mod compression;
 pub mod errors;
 pub mod fakegen;
 pub mod options;
 pub mod schema;
 pub mod sinks;
 pub mod dataframe;
+pub mod ddl;
+pub mod load_script;
+pub mod manifest;
+pub mod resume;
 
 use rayon::prelude::*;
-use rayon::ThreadPoolBuilder;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use chrono::Utc;
 
 const DEFAULT_CHUNK_SIZE: i64 = 10_000;
 
@@ -52,6 +59,8 @@ pub enum DValue {
     Str(String),
     Date(String),
     DateTime(String),
+    /// Sorted, distinct ids of a roaring bitmap column
+    IdSet(Vec<i64>),
     Record(Vec<(String, DValue)>),
 }
 
@@ -72,299 +81,1019 @@ pub enum DType {
     DateTime,
     Latitude,
     Longitude,
+    /// Sequential, gapless primary key starting at `min` (1 by default)
+    Serial,
+    /// Set of distinct ids in the 32-bit id space, loadable into a Postgres `roaringbitmap` column
+    RoaringBitmap,
+    /// Set of distinct ids in the 64-bit id space, loadable into a Postgres `roaringbitmap64` column
+    RoaringBitmap64,
 
     //TODO - For now, let's stick to basic types
     //    Date, Array, Map, Nullable (union/null), Record,
 }
 
+/// Loads a schema and resolves its `references` columns against the given parent schemas
+pub fn load_schema(schema_path: String, parent_schema_paths: &[String]) -> DataGenResult<Schema> {
+    let mut schema = Schema::from_path(schema_path)?;
+    if let Some(column) = schema.dataset.columns.iter().find(|col| col.relationship.is_some()) {
+        return Err(errors::DataGenError::WeirdCase {
+            message: format!("Column {} uses a relationship, which needs a multi-dataset schema and the generate command", column.name)
+        });
+    }
+    let parents = parent_schema_paths
+        .iter()
+        .map(|path| {
+            let parent = Schema::from_path(path.clone())?;
+            parent.validate()?;
+            Ok(parent)
+        })
+        .collect::<DataGenResult<Vec<Schema>>>()?;
+    // Validated once resolved, which tells whether the referenced key spaces hold any key
+    schema.resolve_references(&parents)?;
+    schema.validate()?;
+    Ok(schema)
+}
+
+impl DType {
+    pub fn is_bitmap(&self) -> bool {
+        *self == DType::RoaringBitmap || *self == DType::RoaringBitmap64
+    }
+}
+
 pub fn write_csv_concurrent(
     output_dir: String,       // Output directory path
     schema_path: String,
     num_records: i64,
     delimiter: u8,
     file_splits: usize,
-    _thread_pool_size: usize,
-    _zip_pack_batch_size: usize,
+    thread_pool_size: usize,
+    zip_pack_batch_size: usize,
+) -> DataGenResult<()> {
+    let schema = load_schema(schema_path, &[])?;
+    write_schema_csv_concurrent(
+        output_dir,
+        schema,
+        num_records,
+        delimiter,
+        file_splits,
+        thread_pool_size,
+        thread_pool_size,
+        zip_pack_batch_size,
+        CsvOptions::default(),
+        Compression::default(),
+    )
+}
+
+/// Generates every dataset of a multi-dataset schema file, parents before the datasets that reference them,
+/// followed by the join tables of its relationships. Row count, split count and delimiter come from each
+/// dataset. As with the `csv` command, a `zip_pack_batch_size` of 0 writes one ZIP file per split.
+/// The `seed` overrides the schema file's seed; without either, a random seed is drawn and printed.
+/// Thread pool sizes are the same as for `write_schema_csv_concurrent`. The psql script
+/// `load_<schema name>.psql` loads every file in generation order, so parents come before their children,
+/// and the JSON manifest `manifest_<schema name>.json` describes every file.
+#[allow(clippy::too_many_arguments)]
+pub fn write_multi_schema_csv_concurrent(
+    output_dir: String,
+    schema_path: String,
+    seed: Option<u64>,
+    thread_pool_size: usize,
+    zip_thread_pool_size: usize,
+    zip_pack_batch_size: usize,
+    csv_options: CsvOptions,
+    compression: Compression,
+) -> DataGenResult<()> {
+    let started_at = Utc::now();
+    let mut multi_schema = MultiSchema::from_path(schema_path)?;
+    let schema_sha256 = multi_schema_sha256(&MultiSchema { seed: None, ..multi_schema.clone() })?;
+    // Without a seed, an interrupted run is resumed with the seed it drew
+    let resumed_seed = || multi_schema.datasets.iter()
+        .find_map(|dataset| dataset.num_records.and_then(|num_records| interrupted_seed(&output_dir, &dataset.name, num_records)));
+    let seed = seed.or(multi_schema.seed).or_else(resumed_seed).unwrap_or_else(rand::random);
+    println!("Seed:{}", seed);
+    multi_schema.seed = Some(seed);
+    let schemas = multi_schema.ordered_schemas()?;
+    let mut commands = Vec::new();
+    let mut datasets = Vec::new();
+    for schema in schemas.clone() {
+        let num_records = schema.dataset.num_records.ok_or_else(|| errors::DataGenError::WeirdCase {
+            message: format!("Dataset {} does not declare num_records", schema.dataset.name)
+        })?;
+        let file_splits = schema.dataset.file_splits.unwrap_or(1);
+        let delimiter = parse_delimiter(&schema.dataset.name, &schema.dataset.delimiter)?;
+        println!("Dataset:{}, Total Records:{}, CSV files:{}", schema.dataset.name, num_records, file_splits);
+        let (dataset_commands, dataset) = write_schema_csv(
+            &output_dir,
+            schema,
+            &schema_sha256,
+            num_records,
+            delimiter,
+            file_splits,
+            thread_pool_size,
+            zip_thread_pool_size,
+            zip_pack_batch_size,
+            csv_options.clone(),
+            compression,
+        )?;
+        commands.extend(dataset_commands);
+        datasets.push(dataset);
+    }
+    for relationship in multi_schema.relationships.iter().flatten() {
+        if let Some(join_table) = &relationship.join_table {
+            let (owner, target) = relationship.key_spaces(&schemas)?;
+            println!("Join table:{}, Owner Records:{}", join_table.name, owner.count);
+            let (join_table_commands, dataset) = write_join_table_csv(
+                &output_dir,
+                relationship,
+                &schema_sha256,
+                join_table,
+                owner,
+                target,
+                seed,
+                thread_pool_size,
+                zip_thread_pool_size,
+                zip_pack_batch_size,
+                csv_options.clone(),
+                compression,
+            )?;
+            commands.extend(join_table_commands);
+            datasets.push(dataset);
+        }
+    }
+    load_script::write_load_script(&format!("{}/load_{}.psql", output_dir, multi_schema.name), &commands)?;
+    let manifest = Manifest::new(schema_sha256, seed, started_at, datasets);
+    manifest::write_manifest(&format!("{}/manifest_{}.json", output_dir, multi_schema.name), &manifest)
+}
+
+/// Generates the dataset from `schema.seed`, or from a random seed printed for reuse. For a given
+/// seed, record count and split count, the files are byte-identical however the rows get scheduled.
+/// Rows are generated on a pool of `thread_pool_size` threads and zipped on a pool of
+/// `zip_thread_pool_size` threads; a size of 0 uses one thread per CPU.
+/// The psql script `load_<table>_<num_records>.psql` loading every split and the JSON manifest
+/// `manifest_<table>_<num_records>.json` describing them are written next to the ZIP files.
+#[allow(clippy::too_many_arguments)]
+pub fn write_schema_csv_concurrent(
+    output_dir: String,       // Output directory path
+    mut schema: Schema,
+    num_records: i64,
+    delimiter: u8,
+    file_splits: usize,
+    thread_pool_size: usize,
+    zip_thread_pool_size: usize,
+    zip_pack_batch_size: usize,
+    csv_options: CsvOptions,
+    compression: Compression,
+) -> DataGenResult<()> {
+    let started_at = Utc::now();
+    let schema_sha256 = schema_sha256(&Schema { seed: None, ..schema.clone() })?;
+    // Without a seed, an interrupted run is resumed with the seed it drew
+    let seed = schema.seed
+        .or_else(|| interrupted_seed(&output_dir, &schema.dataset.name, num_records))
+        .unwrap_or_else(rand::random);
+    schema.seed = Some(seed);
+    let output_name = format!("{}_{}", schema.dataset.name, num_records);
+    let (commands, dataset) = write_schema_csv(
+        &output_dir,
+        schema,
+        &schema_sha256,
+        num_records,
+        delimiter,
+        file_splits,
+        thread_pool_size,
+        zip_thread_pool_size,
+        zip_pack_batch_size,
+        csv_options,
+        compression,
+    )?;
+    load_script::write_load_script(&format!("{}/load_{}.psql", output_dir, output_name), &commands)?;
+    let manifest = Manifest::new(schema_sha256, seed, started_at, vec![dataset]);
+    manifest::write_manifest(&format!("{}/manifest_{}.json", output_dir, output_name), &manifest)
+}
+
+/// Writes and zips the CSV splits, returning the `\copy` commands loading them and their manifest.
+/// `schema_sha256` is the hash of the schema the run's manifest records.
+#[allow(clippy::too_many_arguments)]
+fn write_schema_csv(
+    output_dir: &str,
+    mut schema: Schema,
+    schema_sha256: &str,
+    num_records: i64,
+    delimiter: u8,
+    file_splits: usize,
+    thread_pool_size: usize,
+    zip_thread_pool_size: usize,
+    zip_pack_batch_size: usize,
+    csv_options: CsvOptions,
+    compression: Compression,
+) -> DataGenResult<(Vec<String>, DataSetManifest)> {
+    let seed = schema.seed
+        .or_else(|| interrupted_seed(output_dir, &schema.dataset.name, num_records))
+        .unwrap_or_else(rand::random);
+    println!("Dataset:{}, Seed:{}", schema.dataset.name, seed);
+    schema.resolve_seeds(seed);
+    let generator = RecordGenerator::new(&schema)?;
+
+    fs::create_dir_all(output_dir)?;
+    let (state_path, resume) = start_csv_run(
+        output_dir, &schema.dataset.name, num_records, schema_sha256, seed, delimiter, file_splits, zip_pack_batch_size, &csv_options, &compression
+    )?;
+
+    let pool = thread_pool(thread_pool_size)?;
+    let zip_pool = thread_pool(zip_thread_pool_size)?;
+    let batches = zip_pool.install(|| write_compressed_splits(
+        &pool,
+        output_dir,
+        &schema.dataset.name,
+        num_records,
+        file_splits,
+        "csv",
+        &compression,
+        zip_pack_batch_size,
+        resume,
+        |writer, file_path, split_offset, records_for_this_file| {
+            let mut sink = csv_sink::sink(schema.clone(), writer, delimiter, csv_options.clone())
+                .map_err(|e| format!("Failed to create sink for {}: {}", file_path, e))?;
+            write_batches(&mut sink, &generator, file_path, split_offset, records_for_this_file)?;
+            sink.finish()
+                .map_err(|e| format!("Failed to finish {}: {}", file_path, e))?;
+            Ok(records_for_this_file as u64)
+        },
+    ))?;
+    let output = csv_dataset_output(output_dir, &schema, num_records, delimiter, &csv_options, &compression, batches)?;
+    resume::finish(&state_path)?;
+    Ok(output)
+}
+
+/// Seed of the interrupted run of a dataset, which a run without a seed of its own resumes with
+fn interrupted_seed(output_dir: &str, table_name: &str, num_records: i64) -> Option<u64> {
+    resume::read_state(&resume::state_path(output_dir, table_name, num_records)).map(|state| state.seed)
+}
+
+/// Starts a dataset's run, or resumes the interrupted run with the same settings, see `resume`.
+/// Returns the run's state file and whether the files the interrupted run completed can be kept.
+#[allow(clippy::too_many_arguments)]
+fn start_csv_run(
+    output_dir: &str,
+    table_name: &str,
+    num_records: i64,
+    schema_sha256: &str,
+    seed: u64,
+    delimiter: u8,
+    file_splits: usize,
+    zip_pack_batch_size: usize,
+    csv_options: &CsvOptions,
+    compression: &Compression,
+) -> DataGenResult<(String, bool)> {
+    let state_path = resume::state_path(output_dir, table_name, num_records);
+    let state = ResumeState {
+        schema_sha256: schema_sha256.to_string(),
+        seed,
+        file_splits,
+        zip_pack_batch_size,
+        delimiter: (delimiter as char).to_string(),
+        csv_options: csv_options.clone(),
+        compression: compression.to_string(),
+    };
+    let resume = resume::start(&state_path, &state)?;
+    if resume {
+        println!("Resuming {}, keeping the files already complete", table_name);
+    }
+    Ok((state_path, resume))
+}
+
+/// Writes the dataset as `file_splits` Avro object container files named `output_<table>_<num_records>_<split>.avro`,
+/// with blocks compressed by `codec`. The Avro schema is derived from the dataset columns. Seeding and
+/// threading are the same as for `write_schema_csv_concurrent`. The JSON manifest
+/// `manifest_<table>_<num_records>_avro.json` describing the files is written next to them.
+pub fn write_schema_avro_concurrent(
+    output_dir: String,
+    mut schema: Schema,
+    num_records: i64,
+    file_splits: usize,
+    codec: avro_sink::Codec,
+    thread_pool_size: usize,
+) -> DataGenResult<()> {
+    let started_at = Utc::now();
+    let schema_sha256 = schema_sha256(&Schema { seed: None, ..schema.clone() })?;
+    let seed = schema.seed.unwrap_or_else(rand::random);
+    println!("Dataset:{}, Seed:{}", schema.dataset.name, seed);
+    schema.resolve_seeds(seed);
+    let generator = RecordGenerator::new(&schema)?;
+
+    fs::create_dir_all(&output_dir)?;
+
+    let pool = thread_pool(thread_pool_size)?;
+    let files = pool.install(|| write_splits(
+        &output_dir,
+        &schema,
+        num_records,
+        file_splits,
+        "avro",
+        |file| avro_sink::sink(schema.clone(), io::BufWriter::new(file), codec),
+        |sink, file_path, split_offset, records_for_this_file| {
+            write_records(sink, &generator, file_path, split_offset, records_for_this_file)
+        },
+    ))?;
+    let dataset = DataSetManifest::new(&schema.dataset.name, num_records, "avro", compression::Codec::None, files, Vec::new());
+    write_dataset_manifest(&output_dir, schema_sha256, seed, started_at, dataset)
+}
+
+/// Writes the dataset as `file_splits` NDJSON files, one JSON object per record, compressed the same way
+/// as the CSV files of `write_schema_csv_concurrent`. Seeding and threading are the same as well. The JSON
+/// manifest `manifest_<table>_<num_records>_json.json` describing the files is written next to them.
+#[allow(clippy::too_many_arguments)]
+pub fn write_schema_json_concurrent(
+    output_dir: String,
+    mut schema: Schema,
+    num_records: i64,
+    file_splits: usize,
+    thread_pool_size: usize,
+    zip_thread_pool_size: usize,
+    zip_pack_batch_size: usize,
+    compression: Compression,
 ) -> DataGenResult<()> {
-    let schema = Schema::from_path(schema_path.clone())?;
+    let started_at = Utc::now();
+    let schema_sha256 = schema_sha256(&Schema { seed: None, ..schema.clone() })?;
+    let table_name = schema.dataset.name.clone();
+    let seed = schema.seed.unwrap_or_else(rand::random);
+    println!("Dataset:{}, Seed:{}", table_name, seed);
+    schema.resolve_seeds(seed);
+    let generator = RecordGenerator::new(&schema)?;
+
+    fs::create_dir_all(&output_dir)?;
 
+    let pool = thread_pool(thread_pool_size)?;
+    let zip_pool = thread_pool(zip_thread_pool_size)?;
+    let batches = zip_pool.install(|| write_compressed_splits(
+        &pool,
+        &output_dir,
+        &table_name,
+        num_records,
+        file_splits,
+        "json",
+        &compression,
+        zip_pack_batch_size,
+        false,
+        |writer, file_path, split_offset, records_for_this_file| {
+            let mut sink = json_sink::sink(io::BufWriter::new(writer))
+                .map_err(|e| format!("Failed to create sink for {}: {}", file_path, e))?;
+            write_records(&mut sink, &generator, file_path, split_offset, records_for_this_file)?;
+            sink.finish()
+                .map_err(|e| format!("Failed to finish {}: {}", file_path, e))?;
+            Ok(records_for_this_file as u64)
+        },
+    ))?;
+    let dataset = compressed_dataset_manifest(&output_dir, &table_name, num_records, "json", &compression, batches)?;
+    write_dataset_manifest(&output_dir, schema_sha256, seed, started_at, dataset)
+}
+
+/// Writes the dataset as `file_splits` PostgreSQL binary COPY files (`output_<table>_<num_records>_<split>.pgcopy`),
+/// loadable with `COPY <table> FROM '<file>' WITH (FORMAT binary)`, zipped the same way as the CSV files of
+/// `write_schema_csv_concurrent`. Seeding and threading are the same as well. The JSON manifest
+/// `manifest_<table>_<num_records>_pgcopy.json` describing the files is written next to them.
+pub fn write_schema_pgcopy_concurrent(
+    output_dir: String,
+    mut schema: Schema,
+    num_records: i64,
+    file_splits: usize,
+    thread_pool_size: usize,
+    zip_thread_pool_size: usize,
+    zip_pack_batch_size: usize,
+) -> DataGenResult<()> {
+    let started_at = Utc::now();
+    let schema_sha256 = schema_sha256(&Schema { seed: None, ..schema.clone() })?;
     let table_name = schema.dataset.name.clone();
+    let seed = schema.seed.unwrap_or_else(rand::random);
+    println!("Dataset:{}, Seed:{}", table_name, seed);
+    schema.resolve_seeds(seed);
+    let generator = RecordGenerator::new(&schema)?;
+
+    fs::create_dir_all(&output_dir)?;
+
+    let pool = thread_pool(thread_pool_size)?;
+    let zip_pool = thread_pool(zip_thread_pool_size)?;
+    let compression = Compression::default();
+    let batches = zip_pool.install(|| write_compressed_splits(
+        &pool,
+        &output_dir,
+        &table_name,
+        num_records,
+        file_splits,
+        "pgcopy",
+        &compression,
+        zip_pack_batch_size,
+        false,
+        |writer, file_path, split_offset, records_for_this_file| {
+            let mut sink = pgcopy_sink::sink(schema.clone(), io::BufWriter::new(writer))
+                .map_err(|e| format!("Failed to create sink for {}: {}", file_path, e))?;
+            write_records(&mut sink, &generator, file_path, split_offset, records_for_this_file)?;
+            sink.finish()
+                .map_err(|e| format!("Failed to finish {}: {}", file_path, e))?;
+            Ok(records_for_this_file as u64)
+        },
+    ))?;
+    let dataset = compressed_dataset_manifest(&output_dir, &table_name, num_records, "pgcopy", &compression, batches)?;
+    write_dataset_manifest(&output_dir, schema_sha256, seed, started_at, dataset)
+}
+
+/// Writes the dataset as `file_splits` SQL scripts of `INSERT` statements (`output_<table>_<num_records>_<split>.sql`),
+/// each loadable with `psql -f`. Seeding and threading are the same as for `write_schema_csv_concurrent`.
+/// The JSON manifest `manifest_<table>_<num_records>_sql.json` describing the files is written next to them.
+pub fn write_schema_sql_concurrent(
+    output_dir: String,
+    mut schema: Schema,
+    num_records: i64,
+    file_splits: usize,
+    options: sql_sink::SqlOptions,
+    thread_pool_size: usize,
+) -> DataGenResult<()> {
+    let started_at = Utc::now();
+    let schema_sha256 = schema_sha256(&Schema { seed: None, ..schema.clone() })?;
+    let seed = schema.seed.unwrap_or_else(rand::random);
+    println!("Dataset:{}, Seed:{}", schema.dataset.name, seed);
+    schema.resolve_seeds(seed);
+    let generator = RecordGenerator::new(&schema)?;
+
+    fs::create_dir_all(&output_dir)?;
+
+    let pool = thread_pool(thread_pool_size)?;
+    let files = pool.install(|| write_splits(
+        &output_dir,
+        &schema,
+        num_records,
+        file_splits,
+        "sql",
+        |file| sql_sink::sink(schema.clone(), io::BufWriter::new(file), options),
+        |sink, file_path, split_offset, records_for_this_file| {
+            write_records(sink, &generator, file_path, split_offset, records_for_this_file)
+        },
+    ))?;
+    let dataset = DataSetManifest::new(&schema.dataset.name, num_records, "sql", compression::Codec::None, files, Vec::new());
+    write_dataset_manifest(&output_dir, schema_sha256, seed, started_at, dataset)
+}
+
+/// Generates `count` records from row index `first_row` into the sink, in parallel chunks to avoid OOM
+fn write_records<S: Sink>(sink: &mut S, generator: &RecordGenerator, file_path: &str, first_row: i64, count: i64) -> Result<(), String> {
+    let chunk_size = DEFAULT_CHUNK_SIZE;
+    let mut remaining = count;
+
+    while remaining > 0 {
+        let batch_size = std::cmp::min(remaining, chunk_size);
+        let batch_offset = first_row + (count - remaining);
+        let records: Vec<DValue> = (0..batch_size)
+            .into_par_iter()
+            .map(|j| generator.generate(batch_offset + j))
+            .collect();
+
+        for record in records {
+            sink.write(record)
+                .map_err(|e| format!("Failed to write record to {}: {}", file_path, e))?;
+        }
+        remaining -= batch_size;
+    }
+    Ok(())
+}
+
+/// Generates `count` records from row index `first_row` into the sink column by column, a chunk at a time
+/// to avoid OOM, see `Sink::write_batch`
+fn write_batches<S: Sink>(sink: &mut S, generator: &RecordGenerator, file_path: &str, first_row: i64, count: i64) -> Result<(), String> {
+    let mut remaining = count;
+
+    while remaining > 0 {
+        let batch_size = std::cmp::min(remaining, DEFAULT_CHUNK_SIZE);
+        let batch_offset = first_row + (count - remaining);
+        let batch = generator.generate_batch(batch_offset, batch_size as usize)
+            .map_err(|e| format!("Failed to generate records for {}: {}", file_path, e))?;
+        sink.write_batch(batch)
+            .map_err(|e| format!("Failed to write records to {}: {}", file_path, e))?;
+        remaining -= batch_size;
+    }
+    Ok(())
+}
+
+/// Writes the join table of a many-to-many relationship: one `(owner key, target key)` row per
+/// association, split by ranges of owner rows. The pairs come from the same seeded draws as the
+/// relationship's bitmap columns, so both layouts hold exactly the same associations.
+/// `schema_seed` is the seed of the multi-dataset schema the relationship belongs to.
+/// The psql script `load_<join table>_<owner rows>.psql` loading every split and the JSON manifest
+/// `manifest_<join table>_<owner rows>.json` describing them are written next to the ZIP files.
+#[allow(clippy::too_many_arguments)]
+pub fn write_join_table_csv_concurrent(
+    output_dir: String,
+    relationship: &Relationship,
+    owner: KeySpace,
+    target: KeySpace,
+    schema_seed: u64,
+    thread_pool_size: usize,
+    zip_thread_pool_size: usize,
+    zip_pack_batch_size: usize,
+    csv_options: CsvOptions,
+    compression: Compression,
+) -> DataGenResult<()> {
+    let join_table = relationship.join_table.as_ref().ok_or_else(|| errors::DataGenError::WeirdCase {
+        message: format!("Relationship {} has no join_table", relationship.name)
+    })?;
+    let started_at = Utc::now();
+    let schema_sha256 = relationship_sha256(relationship)?;
+    let output_name = format!("{}_{}", join_table.name, owner.count);
+    let (commands, dataset) = write_join_table_csv(
+        &output_dir,
+        relationship,
+        &schema_sha256,
+        join_table,
+        owner,
+        target,
+        schema_seed,
+        thread_pool_size,
+        zip_thread_pool_size,
+        zip_pack_batch_size,
+        csv_options,
+        compression,
+    )?;
+    load_script::write_load_script(&format!("{}/load_{}.psql", output_dir, output_name), &commands)?;
+    let manifest = Manifest::new(schema_sha256, schema_seed, started_at, vec![dataset]);
+    manifest::write_manifest(&format!("{}/manifest_{}.json", output_dir, output_name), &manifest)
+}
+
+/// Writes and zips the join table splits, returning the `\copy` commands loading them and their manifest
+#[allow(clippy::too_many_arguments)]
+fn write_join_table_csv(
+    output_dir: &str,
+    relationship: &Relationship,
+    schema_sha256: &str,
+    join_table: &JoinTable,
+    owner: KeySpace,
+    target: KeySpace,
+    schema_seed: u64,
+    thread_pool_size: usize,
+    zip_thread_pool_size: usize,
+    zip_pack_batch_size: usize,
+    csv_options: CsvOptions,
+    compression: Compression,
+) -> DataGenResult<(Vec<String>, DataSetManifest)> {
+    let schema = join_table.schema();
+    let delimiter = parse_delimiter(&join_table.name, &join_table.delimiter)?;
+    let file_splits = join_table.file_splits.unwrap_or(1);
+    let seed = relationship.seed(schema_seed);
+    let generator = RelationshipGenerator::new(relationship, target, seed)?;
+
+    fs::create_dir_all(output_dir)?;
+    let (state_path, resume) = start_csv_run(
+        output_dir, &join_table.name, owner.count, schema_sha256, seed, delimiter, file_splits, zip_pack_batch_size, &csv_options, &compression
+    )?;
+
+    // The split files are named after the owner row count, as the number of pairs is only known once generated
+    let pool = thread_pool(thread_pool_size)?;
+    let zip_pool = thread_pool(zip_thread_pool_size)?;
+    let batches = zip_pool.install(|| write_compressed_splits(
+        &pool,
+        output_dir,
+        &join_table.name,
+        owner.count,
+        file_splits,
+        "csv",
+        &compression,
+        zip_pack_batch_size,
+        resume,
+        |writer, file_path, split_offset, owner_rows| {
+        let mut sink = csv_sink::sink(schema.clone(), writer, delimiter, csv_options.clone())
+            .map_err(|e| format!("Failed to create sink for {}: {}", file_path, e))?;
+        let mut rows = 0;
+        let chunk_size = DEFAULT_CHUNK_SIZE;
+        let mut remaining = owner_rows;
+
+        while remaining > 0 {
+            let batch_size = std::cmp::min(remaining, chunk_size);
+            let batch_offset = split_offset + (owner_rows - remaining);
+            let id_sets: Vec<Vec<i64>> = (0..batch_size)
+                .into_par_iter()
+                .map(|j| generator.ids(batch_offset + j))
+                .collect();
+
+            for (j, ids) in id_sets.into_iter().enumerate() {
+                let owner_key = owner.start + batch_offset + j as i64;
+                for target_key in ids {
+                    let record = DValue::Record(vec![
+                        (join_table.owner_column.clone(), DValue::Long(owner_key)),
+                        (join_table.target_column.clone(), DValue::Long(target_key)),
+                    ]);
+                    sink.write(record)
+                        .map_err(|e| format!("Failed to write record to {}: {}", file_path, e))?;
+                    rows += 1;
+                }
+            }
+            remaining -= batch_size;
+        }
+        sink.finish()
+            .map_err(|e| format!("Failed to finish {}: {}", file_path, e))?;
+        Ok(rows)
+    }))?;
+    let output = csv_dataset_output(output_dir, &schema, owner.count, delimiter, &csv_options, &compression, batches)?;
+    resume::finish(&state_path)?;
+    Ok(output)
+}
+
+/// Dedicated rayon pool, so a run only uses the threads it was given rather than the global pool
+fn thread_pool(num_threads: usize) -> DataGenResult<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .map_err(|e| errors::DataGenError::WeirdCase {
+            message: format!("Failed to build a thread pool of {} threads: {}", num_threads, e)
+        })
+}
+
+/// Writes the dataset as `file_splits` Parquet files named `output_<table>_<num_records>_<split>.parquet`.
+/// Each split is generated and written `row_group_size` rows at a time, one row group per batch, so
+/// memory stays bounded whatever the split size. Dates and datetimes use the Parquet `DATE` and
+/// `TIMESTAMP(MILLIS)` logical types. Seeding and threading are the same as for `write_schema_csv_concurrent`.
+/// The JSON manifest `manifest_<table>_<num_records>_parquet.json` describing the files is written next to them.
+pub fn write_schema_parquet_concurrent(
+    output_dir: String,
+    mut schema: Schema,
+    num_records: i64,
+    file_splits: usize,
+    row_group_size: usize,
+    thread_pool_size: usize,
+) -> DataGenResult<()> {
+    if row_group_size == 0 {
+        return Err(errors::DataGenError::WeirdCase { message: "Row group size must be positive".to_string() });
+    }
+    let started_at = Utc::now();
+    let schema_sha256 = schema_sha256(&Schema { seed: None, ..schema.clone() })?;
+    let table_name = schema.dataset.name.clone();
+    let seed = schema.seed.unwrap_or_else(rand::random);
+    println!("Dataset:{}, Seed:{}", table_name, seed);
+    schema.resolve_seeds(seed);
+    let generator = RecordGenerator::new(&schema)?;
+
+    fs::create_dir_all(&output_dir)?;
+
+    let pool = thread_pool(thread_pool_size)?;
+    let files = pool.install(|| write_split_files(
+        &output_dir, &schema, num_records, file_splits, "parquet",
+        |file, file_path, split_offset, num_records| {
+            write_parquet_split(&generator, file, split_offset, num_records, row_group_size)
+                .map_err(|e| format!("Failed to write Parquet file {}: {}", file_path, e))
+        },
+    ))?;
+    let dataset = DataSetManifest::new(&table_name, num_records, "parquet", compression::Codec::None, files, Vec::new());
+    write_dataset_manifest(&output_dir, schema_sha256, seed, started_at, dataset)
+}
+
+fn write_parquet_split(
+    generator: &RecordGenerator,
+    file: fs::File,
+    split_offset: i64,
+    num_records: i64,
+    row_group_size: usize,
+) -> DataGenResult<()> {
+    let mut written = 0;
+    // The first batch provides the file schema, even for an empty split
+    let mut batch = dataframe::generate_dataframe_rows(generator, split_offset, std::cmp::min(num_records, row_group_size as i64) as usize)?;
+    let mut writer = polars::prelude::ParquetWriter::new(file).batched(&batch.schema())?;
+    loop {
+        writer.write_batch(&batch)?;
+        written += batch.height() as i64;
+        if written >= num_records {
+            break;
+        }
+        let batch_size = std::cmp::min(num_records - written, row_group_size as i64) as usize;
+        batch = dataframe::generate_dataframe_rows(generator, split_offset + written, batch_size)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// PostgreSQL DDL creating the tables of a schema file: the table of a single-dataset schema, or the tables
+/// of every dataset of a multi-dataset schema in generation order followed by its join tables. With `keys`,
+/// the primary keys and indexes marked in the schema are declared as well.
+pub fn schema_ddl(schema_path: String, keys: bool) -> DataGenResult<String> {
+    let yaml = fs::read_to_string(&schema_path)?;
+    // Scalars are parsed from the text, which lets numbers fill the string fields such as `min`
+    let schemas = if serde_yaml::from_str::<serde_yaml::Value>(&yaml)?.get("datasets").is_some() {
+        let multi_schema = MultiSchema::from(&yaml)?;
+        let mut schemas = multi_schema.ordered_schemas()?;
+        for relationship in multi_schema.relationships.iter().flatten() {
+            schemas.extend(relationship.join_table.iter().map(|join_table| join_table.schema()));
+        }
+        schemas
+    } else {
+        let schema = Schema::from(&yaml)?;
+        schema.validate()?;
+        vec![schema]
+    };
+    Ok(ddl::create_tables(&schemas, keys))
+}
+
+fn parse_delimiter(dataset_name: &str, delimiter: &Option<String>) -> DataGenResult<u8> {
+    match delimiter {
+        Some(delimiter) if delimiter.len() == 1 => Ok(delimiter.as_bytes()[0]),
+        Some(delimiter) => Err(errors::DataGenError::WeirdCase {
+            message: format!("Dataset {} has delimiter {:?}, expected a single character", dataset_name, delimiter)
+        }),
+        None => Ok(b','),
+    }
+}
+
+/// Writes `file_splits` files named `output_<table>_<num_records>_<split>.<extension>` concurrently.
+/// `write_split` receives the sink built by `new_sink`, the file path, the index of the split's first
+/// row and the split's row count; the last split takes the remainder. Each split is written under a
+/// temporary name and renamed once finished, so an interrupted run never leaves a partial split behind.
+/// Returns the manifests of the splits, in split order.
+#[allow(clippy::too_many_arguments)]
+fn write_splits<S, N, F>(
+    output_dir: &str,
+    schema: &Schema,
+    num_records: i64,
+    file_splits: usize,
+    extension: &str,
+    new_sink: N,
+    write_split: F,
+) -> DataGenResult<Vec<FileManifest>>
+where
+    S: Sink,
+    N: Fn(fs::File) -> DataGenResult<S> + Sync,
+    F: Fn(&mut S, &str, i64, i64) -> Result<(), String> + Sync,
+{
+    write_split_files(output_dir, schema, num_records, file_splits, extension, |file, file_path, split_offset, num_records| {
+        let mut sink = new_sink(file)
+            .map_err(|e| format!("Failed to create sink for {}: {}", file_path, e))?;
+        write_split(&mut sink, file_path, split_offset, num_records)?;
+        sink.finish()
+            .map_err(|e| format!("Failed to finish {}: {}", file_path, e))
+    })
+}
+
+/// Writes the `file_splits` files of a dataset in parallel, each under a temporary name renamed once
+/// `write_file` returns. The last split takes the remainder of the records.
+fn write_split_files<F>(
+    output_dir: &str,
+    schema: &Schema,
+    num_records: i64,
+    file_splits: usize,
+    extension: &str,
+    write_file: F,
+) -> DataGenResult<Vec<FileManifest>>
+where
+    F: Fn(fs::File, &str, i64, i64) -> Result<(), String> + Sync,
+{
+    let table_name = &schema.dataset.name;
 
     let records_per_file = num_records / file_splits as i64;
     let remainder = num_records % file_splits as i64;
 
-    let _ = fs::create_dir_all(&output_dir)?;
-    
     // Use Rayon parallel iterator to distribute file writing across thread pool
-    let results: Vec<Result<(), String>> = (0..file_splits)
+    let results: Vec<Result<FileManifest, String>> = (0..file_splits)
         .into_par_iter() // Distribute file writing across Rayon pool
         .map(|i| {
-                let schema_clone = schema.clone();
-                let output_dir_clone = output_dir.clone();
-                let table_name_clone = table_name.clone();
-
                 let records_for_this_file = if i == file_splits - 1 {
                     records_per_file + remainder
                 } else {
                     records_per_file
                 };
-                
+                // First row index of this split, so every split owns a disjoint serial id range
+                let split_offset = i as i64 * records_per_file;
+
                 // Generate file path with zero-padded index
-                let file_path = format!("{}/output_{}_{}_{:02}.csv",
-                                       output_dir_clone, table_name_clone, num_records, i);
-                
-                // Create file and CSV writer
-                let file = fs::File::create(&file_path)
-                    .map_err(|e| format!("Failed to create file {}: {}", file_path, e))?;
-                
-                // Clone schema for generation before moving it into sink
-                let schema_for_gen = schema_clone.clone();
-                
-                let mut sink = csv_sink::sink(schema_clone, file, delimiter)
-                    .map_err(|e| format!("Failed to create CSV sink for {}: {}", file_path, e))?;
-                
-                // Generate records in chunks to avoid OOM
-                let chunk_size = DEFAULT_CHUNK_SIZE;
-                let mut remaining = records_for_this_file;
-                
-                while remaining > 0 {
-                    let batch_size = std::cmp::min(remaining, chunk_size);
-                    let records: Vec<DValue> = (0..batch_size)
-                        .into_par_iter()
-                        .map(|_| fakegen::gen_record_for_schema(schema_for_gen.clone()))
-                        .collect();
-                    
-                    for record in records {
-                        sink.write(record)
-                            .map_err(|e| format!("Failed to write record to {}: {}", file_path, e))?;
-                    }
-                    remaining -= batch_size;
-                }
-                
-            Ok(())
+                let file_name = format!("output_{}_{}_{:02}.{}", table_name, num_records, i, extension);
+                let file_path = format!("{}/{}", output_dir, file_name);
+
+                // Create file and write the split
+                let temp_path = resume::temp_path(&file_path);
+                let file = fs::File::create(&temp_path)
+                    .map_err(|e| format!("Failed to create file {}: {}", temp_path, e))?;
+
+                write_file(file, &file_path, split_offset, records_for_this_file)?;
+                fs::rename(&temp_path, &file_path)
+                    .map_err(|e| format!("Failed to rename {} to {}: {}", temp_path, file_path, e))?;
+                fs::File::open(&file_path)
+                    .map_err(errors::DataGenError::from)
+                    .and_then(|file| manifest::written_file_manifest(&file_name, i, records_for_this_file as u64, file))
+                    .map_err(|e| format!("Failed to hash {}: {}", file_path, e))
         })
         .collect(); // Collect results, waiting for all tasks to complete
-    
+
     // Handle potential errors from parallel execution
-    for res in results {
-        if let Err(e) = res {
-            return Err(errors::DataGenError::WeirdCase { message: e });
-        }
-    }
-    
-    // Create ZIP files in batches concurrently
-    if _zip_pack_batch_size == 0 {
-        // If batch size is 0, create individual ZIP files (current behavior)
-        // Use Rayon parallel iterator for concurrent zipping
-        let zip_results: Vec<Result<(), String>> = (0..file_splits)
-            .into_par_iter() // Distribute zipping across Rayon pool
-            .map(|i| {
-                let csv_file_path = format!("{}/output_{}_{}_{:02}.csv", output_dir, table_name, num_records, i);
-                if Path::new(&csv_file_path).exists() {
-                    create_single_csv_zip(&csv_file_path, &output_dir)
-                        .map_err(|e| format!("Failed to create ZIP for {}: {}", csv_file_path, e))?;
-                    // Delete CSV file after successful ZIP creation
-                    let _ = fs::remove_file(&csv_file_path);
-                }
-                Ok(())
-            })
-            .collect(); // Collect results, waiting for all ZIP operations to complete
-        
-        // Handle potential errors from parallel ZIP operations
-        for res in zip_results {
-            if let Err(e) = res {
-                return Err(errors::DataGenError::WeirdCase { message: e });
-            }
-        }
-    } else {
-        // Create batched ZIP files concurrently
-        let batches: Vec<(usize, usize)> = {
-            let mut batches = Vec::new();
-            let mut batch_start = 0;
-            while batch_start < file_splits {
-                let batch_end = std::cmp::min(batch_start + _zip_pack_batch_size, file_splits);
-                batches.push((batch_start, batch_end));
-                batch_start = batch_end;
-            }
-            batches
-        };
-        
-        // Use Rayon parallel iterator for concurrent batch zipping
-        let zip_results: Vec<Result<(), String>> = batches
-            .into_par_iter() // Distribute batch zipping across Rayon pool
-            .map(|(batch_start, batch_end)| {
-                let csv_files: Vec<String> = (batch_start..batch_end)
-                    .map(|i| format!("{}/output_{}_{}_{:02}.csv", output_dir, table_name, num_records, i))
-                    .filter(|path| Path::new(path).exists())
-                    .collect();
-                
-                if !csv_files.is_empty() {
-                    let start_idx = batch_start;
-                    let end_idx = batch_end - 1;
-                    let zip_filename = format!("output_{}_{}_{:02}_{:02}.csv.zip", table_name, num_records, start_idx, end_idx);
-                    create_batch_csv_zip(&csv_files, &output_dir, &zip_filename)
-                        .map_err(|e| format!("Failed to create batch ZIP {}: {}", zip_filename, e))?;
-                    
-                    // Delete CSV files after successful ZIP creation
-                    for csv_file in &csv_files {
-                        let _ = fs::remove_file(csv_file);
-                    }
-                }
-                Ok(())
-            })
-            .collect(); // Collect results, waiting for all batch ZIP operations to complete
-        
-        // Handle potential errors from parallel batch ZIP operations
-        for res in zip_results {
-            if let Err(e) = res {
-                return Err(errors::DataGenError::WeirdCase { message: e });
-            }
+    results.into_iter()
+        .map(|res| res.map_err(|e| errors::DataGenError::WeirdCase { message: e }))
+        .collect()
+}
+
+/// Describes the files written by `write_compressed_splits` for a CSV dataset. Returns the `\copy` commands
+/// loading the splits and the dataset's manifest.
+fn csv_dataset_output(
+    output_dir: &str,
+    schema: &Schema,
+    num_records: i64,
+    delimiter: u8,
+    csv_options: &CsvOptions,
+    compression: &Compression,
+    batches: Vec<SplitBatch>,
+) -> DataGenResult<(Vec<String>, DataSetManifest)> {
+    // Splits of files kept from an interrupted run are read back from them
+    let files = batches
+        .par_iter()
+        .map(|batch| match &batch.files {
+            Some(files) => Ok(files.clone()),
+            None => read_split_batch(output_dir, batch, delimiter, csv_options, compression),
+        })
+        .collect::<DataGenResult<Vec<Vec<FileManifest>>>>()?;
+    let archives: Vec<Archive> = batches.into_iter().map(|batch| batch.archive).collect();
+    let dataset = DataSetManifest {
+        name: schema.dataset.name.clone(),
+        num_records,
+        format: "csv".to_string(),
+        delimiter: Some((delimiter as char).to_string()),
+        header: Some(csv_options.header),
+        quote: Some((csv_options.quote as char).to_string()),
+        escape: csv_options.escape.map(|escape| (escape as char).to_string()),
+        compression: compression.codec.name().to_string(),
+        files: files.into_iter().flatten().collect(),
+        archives: archives.iter()
+            .map(|archive| manifest::archive_manifest(output_dir, archive))
+            .collect::<DataGenResult<_>>()?,
+    };
+    Ok((load_script::copy_commands(schema, delimiter, csv_options, compression.codec, &archives), dataset))
+}
+
+fn read_split_batch(output_dir: &str, batch: &SplitBatch, delimiter: u8, csv_options: &CsvOptions, compression: &Compression) -> DataGenResult<Vec<FileManifest>> {
+    let path = Path::new(output_dir).join(&batch.archive.file_name);
+    let csv = csv_options.reader(delimiter);
+    let mut files = Vec::new();
+    compression::read_file(&path, compression.codec, &batch.archive.entries[0], |name, reader| {
+        if let Some(k) = batch.archive.entries.iter().position(|entry| entry == name) {
+            files.push(manifest::file_manifest(name, batch.first_split + k, reader, &csv)?);
         }
+        Ok(())
+    })?;
+    if files.len() != batch.archive.entries.len() {
+        return Err(errors::DataGenError::WeirdCase {
+            message: format!("{} holds {} of its {} splits", path.display(), files.len(), batch.archive.entries.len())
+        });
     }
-    
-    Ok(())
+    files.sort_by_key(|file| file.split);
+    Ok(files)
 }
 
-fn create_single_csv_zip(
-    csv_file_path: &str,
+/// Manifest of the splits `write_compressed_splits` wrote in another format than CSV. Those runs are never
+/// resumed, so the manifests of every split are known.
+fn compressed_dataset_manifest(
     output_dir: &str,
-) -> DataGenResult<String> {
-    let csv_path = Path::new(csv_file_path);
-    let csv_filename = csv_path.file_name()
-        .ok_or_else(|| errors::DataGenError::WeirdCase {
-            message: format!("Invalid CSV file path: {}", csv_file_path)
-        })?
-        .to_str()
-        .ok_or_else(|| errors::DataGenError::WeirdCase {
-            message: format!("Invalid UTF-8 in CSV file path: {}", csv_file_path)
-        })?;
-    
-    let zip_filename = format!("{}.zip", csv_filename);
-    let zip_file_path = format!("{}/{}", output_dir, zip_filename);
-    
-    // Create zip file
-    let zip_file = fs::File::create(&zip_file_path)
-        .map_err(|e| errors::DataGenError::WeirdCase {
-            message: format!("Failed to create zip file {}: {}", zip_file_path, e)
-        })?;
-    println!("zip_file_path:{}",zip_file_path);
-    let mut zip = ZipWriter::new(zip_file);
-    let options = FileOptions::default()
-//        .compression_method(zip::CompressionMethod::Stored)
-        .compression_method(zip::CompressionMethod::Deflated)
-        // Optional: Set a compression level 0-9(e.g., 9 for best compression)
-        .compression_level(Some(9))
-        .unix_permissions(0o644);
-    
-    // Add CSV file to zip
-    let mut csv_file = fs::File::open(csv_file_path)
-        .map_err(|e| errors::DataGenError::WeirdCase {
-            message: format!("Failed to open CSV file {}: {}", csv_file_path, e)
-        })?;
-    
-    let mut buffer = Vec::new();
-    use std::io::Read;
-    csv_file.read_to_end(&mut buffer)
-        .map_err(|e| errors::DataGenError::WeirdCase {
-            message: format!("Failed to read CSV file {}: {}", csv_file_path, e)
-        })?;
-    
-    zip.start_file(csv_filename, options)
-        .map_err(|e| errors::DataGenError::WeirdCase {
-            message: format!("Failed to start zip entry for {}: {}", csv_filename, e)
-        })?;
-    
-    zip.write_all(&buffer)
-        .map_err(|e| errors::DataGenError::WeirdCase {
-            message: format!("Failed to write to zip file {}: {}", zip_file_path, e)
-        })?;
-    
-    zip.finish()
-        .map_err(|e| errors::DataGenError::WeirdCase {
-            message: format!("Failed to finish zip file {}: {}", zip_file_path, e)
-        })?;
-    
-    Ok(zip_file_path)
+    table_name: &str,
+    num_records: i64,
+    format: &str,
+    compression: &Compression,
+    batches: Vec<SplitBatch>,
+) -> DataGenResult<DataSetManifest> {
+    let mut files = Vec::new();
+    let mut archives = Vec::new();
+    for batch in batches {
+        files.extend(batch.files.unwrap_or_default());
+        archives.push(manifest::archive_manifest(output_dir, &batch.archive)?);
+    }
+    Ok(DataSetManifest::new(table_name, num_records, format, compression.codec, files, archives))
 }
 
-fn create_batch_csv_zip(
-    csv_files: &[String],
-    output_dir: &str,
-    zip_filename: &str,
-) -> DataGenResult<String> {
-    let zip_file_path = format!("{}/{}", output_dir, zip_filename);
-    
-    // Create zip file
-    let zip_file = fs::File::create(&zip_file_path)
-        .map_err(|e| errors::DataGenError::WeirdCase {
-            message: format!("Failed to create zip file {}: {}", zip_file_path, e)
-        })?;
-    println!("batch_zip_file_path:{}", zip_file_path);
-    let mut zip = ZipWriter::new(zip_file);
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .compression_level(Some(9))
-        .unix_permissions(0o644);
-    
-    // Add each CSV file to the zip
-    use std::io::Read;
-    for csv_file_path in csv_files {
-        let csv_path = Path::new(csv_file_path);
-        let csv_filename = csv_path.file_name()
-            .ok_or_else(|| errors::DataGenError::WeirdCase {
-                message: format!("Invalid CSV file path: {}", csv_file_path)
-            })?
-            .to_str()
-            .ok_or_else(|| errors::DataGenError::WeirdCase {
-                message: format!("Invalid UTF-8 in CSV file path: {}", csv_file_path)
-            })?;
-        
-        let mut csv_file = fs::File::open(csv_file_path)
-            .map_err(|e| errors::DataGenError::WeirdCase {
-                message: format!("Failed to open CSV file {}: {}", csv_file_path, e)
-            })?;
-        
-        let mut buffer = Vec::new();
-        csv_file.read_to_end(&mut buffer)
-            .map_err(|e| errors::DataGenError::WeirdCase {
-                message: format!("Failed to read CSV file {}: {}", csv_file_path, e)
-            })?;
-        
-        zip.start_file(csv_filename, options)
-            .map_err(|e| errors::DataGenError::WeirdCase {
-                message: format!("Failed to start zip entry for {}: {}", csv_filename, e)
-            })?;
-        
-        zip.write_all(&buffer)
-            .map_err(|e| errors::DataGenError::WeirdCase {
-                message: format!("Failed to write to zip file {}: {}", zip_file_path, e)
-            })?;
+/// Writes the manifest `manifest_<table>_<num_records>_<format>.json` of a dataset written in another format
+/// than CSV, named after the format so it does not replace the manifest of the same dataset written as CSV
+fn write_dataset_manifest(output_dir: &str, schema_sha256: String, seed: u64, started_at: chrono::DateTime<Utc>, dataset: DataSetManifest) -> DataGenResult<()> {
+    let path = format!("{}/manifest_{}_{}_{}.json", output_dir, dataset.name, dataset.num_records, dataset.format);
+    manifest::write_manifest(&path, &Manifest::new(schema_sha256, seed, started_at, vec![dataset]))
+}
+
+/// SHA-256 of a schema's YAML serialization
+fn schema_sha256(schema: &Schema) -> DataGenResult<String> {
+    Ok(manifest::sha256_hex(serde_yaml::to_string(schema)?.as_bytes()))
+}
+
+fn multi_schema_sha256(multi_schema: &MultiSchema) -> DataGenResult<String> {
+    Ok(manifest::sha256_hex(serde_yaml::to_string(multi_schema)?.as_bytes()))
+}
+
+fn relationship_sha256(relationship: &Relationship) -> DataGenResult<String> {
+    Ok(manifest::sha256_hex(serde_yaml::to_string(relationship)?.as_bytes()))
+}
+
+/// Checks a directory against every `manifest*.json` file it holds, see `manifest::verify`.
+/// Returns the mismatches found, empty when every file is intact.
+pub fn verify_output(output_dir: String) -> DataGenResult<Vec<String>> {
+    let mut manifest_paths: Vec<_> = fs::read_dir(&output_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.file_name().and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("manifest") && name.ends_with(".json")))
+        .collect();
+    if manifest_paths.is_empty() {
+        return Err(errors::DataGenError::WeirdCase { message: format!("No manifest found in {}", output_dir) });
     }
-    
-    zip.finish()
-        .map_err(|e| errors::DataGenError::WeirdCase {
-            message: format!("Failed to finish zip file {}: {}", zip_file_path, e)
-        })?;
-    
-    Ok(zip_file_path)
+    manifest_paths.sort();
+    let mut problems = Vec::new();
+    for path in manifest_paths {
+        let manifest = manifest::read_manifest(&path)?;
+        problems.extend(manifest::verify(&output_dir, &manifest)?);
+    }
+    Ok(problems)
+}
+
+/// File written by `write_compressed_splits` and the splits it holds
+struct SplitBatch {
+    archive: Archive,
+    /// Index of the first split in the file
+    first_split: usize,
+    /// Manifests of the splits, `None` for a file kept from an interrupted run
+    files: Option<Vec<FileManifest>>,
 }
 
+/// Writes `file_splits` splits named `output_<table>_<num_records>_<split>.<extension>` straight into
+/// files compressed with `compression`, so no split touches the disk uncompressed unless asked to. Container
+/// codecs pack one split per file when `zip_pack_batch_size` is 0 and otherwise `zip_pack_batch_size` splits
+/// per file, named `output_<table>_<num_records>_<first>_<last>.<extension>.zip`; the other codecs write a
+/// file per split. `write_split` receives the writer of the split, its path, the index of its first row and
+/// its row count, the last split taking the remainder, and returns the number of rows written.
+///
+/// Files are written concurrently on the current pool and their splits generated on `pool`. Each file is
+/// written under a temporary name and renamed once finished; with `resume`, the files an interrupted run
+/// finished are kept. Returns the files in split order.
+#[allow(clippy::too_many_arguments)]
+fn write_compressed_splits<F>(
+    pool: &ThreadPool,
+    output_dir: &str,
+    table_name: &str,
+    num_records: i64,
+    file_splits: usize,
+    extension: &str,
+    compression: &Compression,
+    zip_pack_batch_size: usize,
+    resume: bool,
+    write_split: F,
+) -> DataGenResult<Vec<SplitBatch>>
+where
+    F: Fn(&mut dyn Write, &str, i64, i64) -> Result<u64, String> + Sync,
+{
+    let records_per_file = num_records / file_splits as i64;
+    let remainder = num_records % file_splits as i64;
+    let batched = compression.codec.is_container() && zip_pack_batch_size > 0;
+    let batch_size = if batched { zip_pack_batch_size } else { 1 };
+
+    let results: Vec<Result<SplitBatch, String>> = (0..file_splits)
+        .step_by(batch_size)
+        .collect::<Vec<usize>>()
+        .into_par_iter()
+        .map(|batch_start| {
+            let batch_end = std::cmp::min(batch_start + batch_size, file_splits);
+            let entries: Vec<String> = (batch_start..batch_end)
+                .map(|i| format!("output_{}_{}_{:02}.{}", table_name, num_records, i, extension))
+                .collect();
+            let file_name = if batched {
+                format!("output_{}_{}_{:02}_{:02}.{}{}", table_name, num_records, batch_start, batch_end - 1, extension, compression.codec.suffix())
+            } else {
+                format!("{}{}", entries[0], compression.codec.suffix())
+            };
+            let file_path = format!("{}/{}", output_dir, file_name);
+            let archive = Archive { file_name, entries };
+
+            // Finished by an interrupted run this one resumes
+            if resume && Path::new(&file_path).exists() {
+                return Ok(SplitBatch { archive, first_split: batch_start, files: None });
+            }
+
+            // Written under a temporary name, so a file found by a resumed run is always complete
+            let temp_path = resume::temp_path(&file_path);
+            let mut files = Vec::with_capacity(archive.entries.len());
+            compression::write_file(&temp_path, compression, &archive.entries, |k, writer| {
+                let i = batch_start + k;
+                let records_for_this_file = if i == file_splits - 1 {
+                    records_per_file + remainder
+                } else {
+                    records_per_file
+                };
+                // First row index of this split, so every split owns a disjoint serial id range
+                let split_offset = i as i64 * records_per_file;
+                let split_path = format!("{}/{}", output_dir, archive.entries[k]);
+
+                let mut entry = manifest::HashingWriter::new(writer);
+                let rows = pool.install(|| write_split(&mut entry, &split_path, split_offset, records_for_this_file))
+                    .map_err(|message| errors::DataGenError::WeirdCase { message })?;
+                let (bytes, sha256) = entry.finish();
+                files.push(FileManifest { name: archive.entries[k].clone(), split: i, rows, bytes, sha256 });
+                Ok(())
+            }).map_err(|e| format!("Failed to write {}: {}", file_path, e))?;
+            fs::rename(&temp_path, &file_path)
+                .map_err(|e| format!("Failed to rename {} to {}: {}", temp_path, file_path, e))?;
+            Ok(SplitBatch { archive, first_split: batch_start, files: Some(files) })
+        })
+        .collect(); // Collect results, waiting for all files to be written
+
+    results.into_iter()
+        .map(|res| res.map_err(|e| errors::DataGenError::WeirdCase { message: e }))
+        .collect()
+}
 
 #[cfg(test)]
 mod tests {
     use std::fs;
-    use std::fs::File;
-    use std::io::{BufRead, BufReader};
+    use std::io::{Read, Write};
     use std::path::Path;
 
-    use serde_json::Value;
-
-    fn get_file_writer(path: &str) -> File {
-        fs::create_dir_all(Path::new(path).parent().unwrap()).unwrap();
-        let writer = File::create(path).expect("Output File Path not found");
-        writer
-    }
-
     #[test]
     fn test_write_csv_concurrent() {
         // Test with 4 splits and batch size 2 (should create 2 ZIP files containing 2 CSVs each)
@@ -375,7 +1104,7 @@ mod tests {
             output_dir.to_string(),
             "./test_data/schema_simple.yaml".to_string(),
             1000,
-            ',' as u8,
+            b',',
             4,
             thread_pool_size, // Pass new parameter
             zip_pack_batch_size, // New parameter
@@ -403,6 +1132,8 @@ mod tests {
         for zip_path in &zip_files_exist {
             let _ = fs::remove_file(zip_path);
         }
+        let _ = fs::remove_file(format!("{}/load_person_table_1000.psql", output_dir));
+        let _ = fs::remove_file(format!("{}/manifest_person_table_1000.json", output_dir));
         let _ = fs::remove_dir(output_dir);
     }
 
@@ -416,7 +1147,7 @@ mod tests {
             output_dir.to_string(),
             "./test_data/schema_simple.yaml".to_string(),
             1000,
-            ',' as u8,
+            b',',
             4,
             thread_pool_size,
             zip_pack_batch_size,
@@ -445,6 +1176,8 @@ mod tests {
         for zip_path in &zip_files_exist {
             let _ = fs::remove_file(zip_path);
         }
+        let _ = fs::remove_file(format!("{}/load_person_table_1000.psql", output_dir));
+        let _ = fs::remove_file(format!("{}/manifest_person_table_1000.json", output_dir));
         let _ = fs::remove_dir(output_dir);
     }
 
@@ -458,7 +1191,7 @@ mod tests {
             output_dir.to_string(),
             "./test_data/schema_simple.yaml".to_string(),
             600, // Smaller dataset for faster test
-            ',' as u8,
+            b',',
             10,  // 10 file splits
             thread_pool_size,
             zip_pack_batch_size,
@@ -488,6 +1221,664 @@ mod tests {
         for zip_path in &expected_zip_files {
             let _ = fs::remove_file(zip_path);
         }
+        let _ = fs::remove_file(format!("{}/load_person_table_600.psql", output_dir));
+        let _ = fs::remove_file(format!("{}/manifest_person_table_600.json", output_dir));
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_csv_serial_ids_across_splits() {
+        // 1000 records over 3 uneven splits must hold every id from 1 to 1000 exactly once
+        let output_dir = "./output_data/serial_test";
+        super::write_csv_concurrent(
+            output_dir.to_string(),
+            "./test_data/schema_serial.yaml".to_string(),
+            1000,
+            b',',
+            3,
+            2,
+            0,
+        ).unwrap();
+
+        let zip_files: Vec<String> = (0..3).map(|i| {
+            format!("{}/output_serial_table_1000_{:02}.csv.zip", output_dir, i)
+        }).collect();
+
+        let mut ids: Vec<i64> = Vec::new();
+        for zip_path in &zip_files {
+            let mut archive = zip::ZipArchive::new(fs::File::open(zip_path).unwrap()).unwrap();
+            let mut content = String::new();
+            archive.by_index(0).unwrap().read_to_string(&mut content).unwrap();
+            ids.extend(content.lines().map(|line| line.split(',').next().unwrap().parse::<i64>().unwrap()));
+        }
+        ids.sort_unstable();
+        assert_eq!(ids, (1..=1000).collect::<Vec<i64>>());
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(format!("{}/load_serial_table_1000.psql", output_dir));
+        let _ = fs::remove_file(format!("{}/manifest_serial_table_1000.json", output_dir));
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_manifest_and_verify_output() {
+        let output_dir = "./output_data/manifest_test";
+        let mut schema = super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap();
+        schema.seed = Some(7);
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema, 100, b'|', 3, 2, 2, 2, super::CsvOptions::default(), super::Compression::default()).unwrap();
+
+        let manifest_path = format!("{}/manifest_serial_table_100.json", output_dir);
+        let manifest = super::manifest::read_manifest(Path::new(&manifest_path)).unwrap();
+        assert_eq!(manifest.seed, 7);
+        let dataset = &manifest.datasets[0];
+        assert_eq!(dataset.files.iter().map(|file| (file.split, file.rows)).collect::<Vec<_>>(), vec![(0, 33), (1, 33), (2, 34)]);
+        assert_eq!(dataset.archives.iter().map(|archive| archive.name.as_str()).collect::<Vec<_>>(),
+                   vec!["output_serial_table_100_00_01.csv.zip", "output_serial_table_100_02_02.csv.zip"]);
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+
+        // A truncated archive and a missing one are both reported
+        let zip_files: Vec<String> = dataset.archives.iter().map(|archive| format!("{}/{}", output_dir, archive.name)).collect();
+        let bytes = fs::read(&zip_files[0]).unwrap();
+        fs::write(&zip_files[0], &bytes[..bytes.len() / 2]).unwrap();
+        fs::remove_file(&zip_files[1]).unwrap();
+        let problems = super::verify_output(output_dir.to_string()).unwrap();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("output_serial_table_100_00_01.csv.zip: "));
+        assert_eq!(problems[2], "output_serial_table_100_02_02.csv.zip: missing");
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(format!("{}/load_serial_table_100.psql", output_dir));
+        let _ = fs::remove_file(manifest_path);
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_csv_with_compression() {
+        let output_dir = "./output_data/compression_csv_test";
+        let schema = || super::Schema { seed: Some(5), ..super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap() };
+        let zstd = super::Compression::new(super::compression::Codec::Zstd, Some(19)).unwrap();
+        let tar = super::Compression::new(super::compression::Codec::Tar, None).unwrap();
+        let load_script = format!("{}/load_serial_table_100.psql", output_dir);
+        let manifest_path = format!("{}/manifest_serial_table_100.json", output_dir);
+
+        // One file per split, whatever the batch size
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema(), 100, b',', 3, 2, 2, 2, super::CsvOptions::default(), zstd).unwrap();
+        let zstd_files: Vec<String> = (0..3).map(|i| format!("{}/output_serial_table_100_{:02}.csv.zst", output_dir, i)).collect();
+        let mut rows = 0;
+        for zstd_file in &zstd_files {
+            let content = zstd::stream::decode_all(fs::File::open(zstd_file).unwrap()).unwrap();
+            rows += content.iter().filter(|&&byte| byte == b'\n').count();
+        }
+        assert_eq!(rows, 100);
+        assert!(fs::read_to_string(&load_script).unwrap().contains("FROM PROGRAM 'zstd -dc \"output_serial_table_100_02.csv.zst\"'"));
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+
+        // Tar files are batched like ZIP files and hold the same splits
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema(), 100, b',', 3, 2, 2, 2, super::CsvOptions::default(), tar).unwrap();
+        let tar_files = [
+            format!("{}/output_serial_table_100_00_01.csv.tar", output_dir),
+            format!("{}/output_serial_table_100_02_02.csv.tar", output_dir),
+        ];
+        let mut archive = tar::Archive::new(fs::File::open(&tar_files[1]).unwrap());
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        assert_eq!(content, zstd::stream::decode_all(fs::File::open(&zstd_files[2]).unwrap()).unwrap());
+        assert!(fs::read_to_string(&load_script).unwrap().contains("FROM PROGRAM 'tar -xOf \"output_serial_table_100_00_01.csv.tar\" \"output_serial_table_100_01.csv\"'"));
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+
+        // Clean up test files
+        for path in zstd_files.iter().chain(tar_files.iter()) {
+            let _ = fs::remove_file(path);
+        }
+        let _ = fs::remove_file(load_script);
+        let _ = fs::remove_file(manifest_path);
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_csv_with_dialect() {
+        let output_dir = "./output_data/dialect_csv_test";
+        let schema = super::Schema { seed: Some(5), ..super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap() };
+        let csv_options = super::CsvOptions {
+            header: true,
+            escape: Some(b'\\'),
+            terminator: super::csv_sink::Terminator::Crlf,
+            null: "\\N".to_string(),
+            postgres: true,
+            ..super::CsvOptions::default()
+        };
+        let uncompressed = super::Compression::new(super::compression::Codec::None, None).unwrap();
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema, 100, b'|', 2, 2, 2, 2, csv_options, uncompressed).unwrap();
+
+        let csv_files: Vec<String> = (0..2).map(|i| format!("{}/output_serial_table_100_{:02}.csv", output_dir, i)).collect();
+        for csv_file in &csv_files {
+            let content = fs::read_to_string(csv_file).unwrap();
+            assert!(content.starts_with("id|order_id|name\r\n"));
+            assert_eq!(content.matches("\r\n").count(), 51);
+        }
+        let load_script = format!("{}/load_serial_table_100.psql", output_dir);
+        assert!(fs::read_to_string(&load_script).unwrap()
+            .contains("FROM 'output_serial_table_100_01.csv' WITH (FORMAT csv, DELIMITER '|', HEADER, NULL '\\N', ESCAPE '\\')"));
+        // The header rows are not counted as records
+        let manifest_path = format!("{}/manifest_serial_table_100.json", output_dir);
+        let manifest = super::manifest::read_manifest(Path::new(&manifest_path)).unwrap();
+        assert_eq!(manifest.datasets[0].files.iter().map(|file| file.rows).sum::<u64>(), 100);
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+
+        // Clean up test files
+        for path in &csv_files {
+            let _ = fs::remove_file(path);
+        }
+        let _ = fs::remove_file(load_script);
+        let _ = fs::remove_file(manifest_path);
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_resume_interrupted_csv_run() {
+        let output_dir = "./output_data/resume_test";
+        let schema = || super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap();
+        let zip_files = [
+            format!("{}/output_serial_table_100_00_01.csv.zip", output_dir),
+            format!("{}/output_serial_table_100_02_03.csv.zip", output_dir),
+        ];
+        super::write_schema_csv_concurrent(output_dir.to_string(), super::Schema { seed: Some(9), ..schema() }, 100, b',', 4, 2, 2, 2, super::CsvOptions::default(), super::Compression::default()).unwrap();
+        let second_zip = fs::read(&zip_files[1]).unwrap();
+
+        // Interrupted with the first ZIP file finished and the second one half-written
+        let state = super::ResumeState {
+            schema_sha256: super::schema_sha256(&schema()).unwrap(),
+            seed: 9,
+            file_splits: 4,
+            zip_pack_batch_size: 2,
+            delimiter: ",".to_string(),
+            csv_options: super::CsvOptions::default(),
+            compression: super::Compression::default().to_string(),
+        };
+        super::resume::start(&super::resume::state_path(output_dir, "serial_table", 100), &state).unwrap();
+        let mut zip = zip::ZipWriter::new(fs::File::create(&zip_files[0]).unwrap());
+        for split in &["output_serial_table_100_00.csv", "output_serial_table_100_01.csv"] {
+            zip.start_file(*split, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(b"kept\n").unwrap();
+        }
+        zip.finish().unwrap();
+        fs::remove_file(&zip_files[1]).unwrap();
+        fs::write(super::resume::temp_path(&zip_files[1]), "half").unwrap();
+
+        // The seed comes from the interrupted run and only the second ZIP file is written again
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema(), 100, b',', 4, 2, 2, 2, super::CsvOptions::default(), super::Compression::default()).unwrap();
+        let mut archive = zip::ZipArchive::new(fs::File::open(&zip_files[0]).unwrap()).unwrap();
+        let mut content = String::new();
+        archive.by_name("output_serial_table_100_01.csv").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "kept\n");
+        assert_eq!(fs::read(&zip_files[1]).unwrap(), second_zip);
+        assert!(!Path::new(&super::resume::temp_path(&zip_files[1])).exists());
+        assert!(!Path::new(&super::resume::state_path(output_dir, "serial_table", 100)).exists());
+        let manifest_path = format!("{}/manifest_serial_table_100.json", output_dir);
+        assert_eq!(super::manifest::read_manifest(Path::new(&manifest_path)).unwrap().seed, 9);
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(format!("{}/load_serial_table_100.psql", output_dir));
+        let _ = fs::remove_file(manifest_path);
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_resume_interrupted_csv_run_without_seed() {
+        let output_dir = "./output_data/resume_unseeded_test";
+        let schema = || super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap();
+        let zip_files = [
+            format!("{}/output_serial_table_100_00_01.csv.zip", output_dir),
+            format!("{}/output_serial_table_100_02_03.csv.zip", output_dir),
+        ];
+        let manifest_path = format!("{}/manifest_serial_table_100.json", output_dir);
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema(), 100, b',', 4, 2, 2, 2, super::CsvOptions::default(), super::Compression::default()).unwrap();
+        let manifest = super::manifest::read_manifest(Path::new(&manifest_path)).unwrap();
+        let seed = manifest.seed;
+
+        // Interrupted with the first ZIP file finished and the second one missing
+        let state = super::ResumeState {
+            schema_sha256: manifest.schema_sha256,
+            seed,
+            file_splits: 4,
+            zip_pack_batch_size: 2,
+            delimiter: ",".to_string(),
+            csv_options: super::CsvOptions::default(),
+            compression: super::Compression::default().to_string(),
+        };
+        super::resume::start(&super::resume::state_path(output_dir, "serial_table", 100), &state).unwrap();
+        let mut zip = zip::ZipWriter::new(fs::File::create(&zip_files[0]).unwrap());
+        for split in &["output_serial_table_100_00.csv", "output_serial_table_100_01.csv"] {
+            zip.start_file(*split, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(b"kept\n").unwrap();
+        }
+        zip.finish().unwrap();
+        fs::remove_file(&zip_files[1]).unwrap();
+
+        // Neither run is given a seed, the second one reuses the seed the first one drew
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema(), 100, b',', 4, 2, 2, 2, super::CsvOptions::default(), super::Compression::default()).unwrap();
+        let mut archive = zip::ZipArchive::new(fs::File::open(&zip_files[0]).unwrap()).unwrap();
+        let mut content = String::new();
+        archive.by_name("output_serial_table_100_00.csv").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "kept\n");
+        assert!(Path::new(&zip_files[1]).exists());
+        assert_eq!(super::manifest::read_manifest(Path::new(&manifest_path)).unwrap().seed, seed);
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(format!("{}/load_serial_table_100.psql", output_dir));
+        let _ = fs::remove_file(manifest_path);
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_resume_interrupted_csv_run_of_unbatched_splits() {
+        let output_dir = "./output_data/resume_unbatched_test";
+        let schema = || super::Schema { seed: Some(9), ..super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap() };
+        let zip_files: Vec<String> = (0..4).map(|i| {
+            format!("{}/output_serial_table_100_{:02}.csv.zip", output_dir, i)
+        }).collect();
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema(), 100, b',', 4, 2, 2, 0, super::CsvOptions::default(), super::Compression::default()).unwrap();
+        let last_zip = fs::read(&zip_files[3]).unwrap();
+
+        // With the default batch size of 0, interrupted after three of the four splits
+        let state = super::ResumeState {
+            schema_sha256: super::schema_sha256(&super::Schema { seed: None, ..schema() }).unwrap(),
+            seed: 9,
+            file_splits: 4,
+            zip_pack_batch_size: 0,
+            delimiter: ",".to_string(),
+            csv_options: super::CsvOptions::default(),
+            compression: super::Compression::default().to_string(),
+        };
+        super::resume::start(&super::resume::state_path(output_dir, "serial_table", 100), &state).unwrap();
+        for (i, zip_path) in zip_files[..3].iter().enumerate() {
+            let mut zip = zip::ZipWriter::new(fs::File::create(zip_path).unwrap());
+            zip.start_file(format!("output_serial_table_100_{:02}.csv", i), zip::write::FileOptions::default()).unwrap();
+            zip.write_all(b"kept\n").unwrap();
+            zip.finish().unwrap();
+        }
+        fs::remove_file(&zip_files[3]).unwrap();
+
+        // Every finished split is kept and only the last one is written again
+        super::write_schema_csv_concurrent(output_dir.to_string(), schema(), 100, b',', 4, 2, 2, 0, super::CsvOptions::default(), super::Compression::default()).unwrap();
+        for zip_path in &zip_files[..3] {
+            assert_eq!(read_zip_entries(zip_path), "kept\n");
+        }
+        assert_eq!(fs::read(&zip_files[3]).unwrap(), last_zip);
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(format!("{}/load_serial_table_100.psql", output_dir));
+        let _ = fs::remove_file(format!("{}/manifest_serial_table_100.json", output_dir));
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_regenerate_interrupted_csv_run_of_a_changed_schema() {
+        let output_dir = "./output_data/resume_changed_schema_test";
+        let schema = || super::Schema { seed: Some(9), ..super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap() };
+        let zip_files = [
+            format!("{}/output_serial_table_100_00_01.csv.zip", output_dir),
+            format!("{}/output_serial_table_100_02_03.csv.zip", output_dir),
+        ];
+        let mut changed = schema();
+        changed.dataset.columns[1].min = Some("2000".to_string());
+        super::write_schema_csv_concurrent(output_dir.to_string(), changed.clone(), 100, b',', 4, 2, 2, 2, super::CsvOptions::default(), super::Compression::default()).unwrap();
+        let first_zip = fs::read(&zip_files[0]).unwrap();
+
+        // Interrupted with the first ZIP file finished, before a column of the schema changed
+        let state = super::ResumeState {
+            schema_sha256: super::schema_sha256(&super::Schema { seed: None, ..schema() }).unwrap(),
+            seed: 9,
+            file_splits: 4,
+            zip_pack_batch_size: 2,
+            delimiter: ",".to_string(),
+            csv_options: super::CsvOptions::default(),
+            compression: super::Compression::default().to_string(),
+        };
+        super::resume::start(&super::resume::state_path(output_dir, "serial_table", 100), &state).unwrap();
+        fs::write(&zip_files[0], "stale").unwrap();
+
+        // The first ZIP file no longer matches the schema, so it is written again
+        super::write_schema_csv_concurrent(output_dir.to_string(), changed, 100, b',', 4, 2, 2, 2, super::CsvOptions::default(), super::Compression::default()).unwrap();
+        assert_eq!(fs::read(&zip_files[0]).unwrap(), first_zip);
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(format!("{}/load_serial_table_100.psql", output_dir));
+        let _ = fs::remove_file(format!("{}/manifest_serial_table_100.json", output_dir));
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_multi_schema_csv_concurrent() {
+        let output_dir = "./output_data/multi_schema_test";
+        super::write_multi_schema_csv_concurrent(
+            output_dir.to_string(),
+            "./test_data/schema_multi.yaml".to_string(),
+            None,
+            2,
+            1,
+            0,
+            super::CsvOptions::default(),
+            super::Compression::default(),
+        ).unwrap();
+
+        // Each dataset uses its own row count and split count, one ZIP file per split
+        let zip_files: Vec<String> = vec![
+            format!("{}/output_authors_50_00.csv.zip", output_dir),
+            format!("{}/output_books_100_00.csv.zip", output_dir),
+            format!("{}/output_books_100_01.csv.zip", output_dir),
+            format!("{}/output_reviews_300_00.csv.zip", output_dir),
+            format!("{}/output_reviews_300_01.csv.zip", output_dir),
+            format!("{}/output_reviews_300_02.csv.zip", output_dir),
+        ];
+        for zip_path in &zip_files {
+            assert!(Path::new(zip_path).exists(), "Expected ZIP file to exist: {}", zip_path);
+        }
+
+        // Reviews use their own delimiter and only reference existing books
+        let content = read_zip_entries(&zip_files[3]);
+        for line in content.lines() {
+            let book_id: i64 = line.split('|').nth(2).unwrap().parse().unwrap();
+            assert!((1..=100).contains(&book_id));
+        }
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(format!("{}/load_multi_schema.psql", output_dir));
+        let _ = fs::remove_file(format!("{}/manifest_multi_schema.json", output_dir));
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    fn read_zip_entries(zip_path: &str) -> String {
+        let mut archive = zip::ZipArchive::new(fs::File::open(zip_path).unwrap()).unwrap();
+        let mut content = String::new();
+        for i in 0..archive.len() {
+            archive.by_index(i).unwrap().read_to_string(&mut content).unwrap();
+        }
+        content
+    }
+
+    #[test]
+    fn test_write_relationship_join_table_matches_bitmaps() {
+        let output_dir = "./output_data/relationship_test";
+        super::write_multi_schema_csv_concurrent(
+            output_dir.to_string(),
+            "./test_data/schema_relationship.yaml".to_string(),
+            None,
+            2,
+            1,
+            0,
+            super::CsvOptions::default(),
+            super::Compression::default(),
+        ).unwrap();
+
+        let bitmap_files: Vec<String> = (0..3).map(|i| format!("{}/output_roaring_fkey_books_40_{:02}.csv.zip", output_dir, i)).collect();
+        let join_table_files: Vec<String> = (0..2).map(|i| format!("{}/output_books_orders_40_{:02}.csv.zip", output_dir, i)).collect();
+        let mut zip_files: Vec<String> = vec![
+            format!("{}/output_books_40_00.csv.zip", output_dir),
+            format!("{}/output_orders_25_00.csv.zip", output_dir),
+        ];
+        zip_files.extend(bitmap_files.iter().cloned());
+        zip_files.extend(join_table_files.iter().cloned());
+
+        // Aggregate the join table rows per book
+        let mut from_join_table: Vec<Vec<i64>> = vec![Vec::new(); 40];
+        for line in join_table_files.iter().map(|zip_path| read_zip_entries(zip_path)).collect::<String>().lines() {
+            let mut fields = line.split(',').map(|field| field.parse::<i64>().unwrap());
+            let (book_id, order_id) = (fields.next().unwrap(), fields.next().unwrap());
+            assert!((100..125).contains(&order_id));
+            from_join_table[(book_id - 1) as usize].push(order_id);
+        }
+
+        // Parse the bitmap column of every book
+        let mut from_bitmaps: Vec<Vec<i64>> = vec![Vec::new(); 40];
+        for line in bitmap_files.iter().map(|zip_path| read_zip_entries(zip_path)).collect::<String>().lines() {
+            let (id, ids) = line.split_at(line.find(',').unwrap());
+            let ids = ids.trim_start_matches(",\"{").trim_end_matches("}\"");
+            from_bitmaps[(id.parse::<i64>().unwrap() - 1) as usize] = ids.split(',')
+                .filter(|id| !id.is_empty())
+                .map(|id| id.parse::<i64>().unwrap())
+                .collect();
+        }
+
+        for ids in from_join_table.iter_mut() {
+            ids.sort_unstable();
+        }
+        assert_eq!(from_join_table, from_bitmaps);
+        assert!(from_bitmaps.iter().any(|ids| !ids.is_empty()));
+
+        // The load script copies every split, parents first and join tables last
+        let script_path = format!("{}/load_relationship_schema.psql", output_dir);
+        let script = fs::read_to_string(&script_path).unwrap();
+        let tables: Vec<&str> = script.lines()
+            .filter(|line| line.starts_with("\\copy"))
+            .map(|line| line.split('"').nth(1).unwrap())
+            .collect();
+        assert_eq!(tables, vec!["books", "orders", "roaring_fkey_books", "roaring_fkey_books", "roaring_fkey_books", "books_orders", "books_orders"]);
+        assert!(script.contains("FROM PROGRAM 'unzip -p \"output_books_orders_40_01.csv.zip\" \"output_books_orders_40_01.csv\"' WITH (FORMAT csv, DELIMITER ',')"));
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(script_path);
+        let _ = fs::remove_file(format!("{}/manifest_relationship_schema.json", output_dir));
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_csv_with_seed_is_reproducible() {
+        let write = |output_dir: &str, seed: u64, threads: usize| -> Vec<Vec<u8>> {
+            let mut schema = super::load_schema("./test_data/schema_simple.yaml".to_string(), &[]).unwrap();
+            schema.seed = Some(seed);
+            super::write_schema_csv_concurrent(output_dir.to_string(), schema, 1000, b',', 3, threads, threads, 0, super::CsvOptions::default(), super::Compression::default()).unwrap();
+            let zip_files: Vec<String> = (0..3).map(|i| {
+                format!("{}/output_person_table_1000_{:02}.csv.zip", output_dir, i)
+            }).collect();
+            let contents = zip_files.iter().map(|zip_path| fs::read(zip_path).unwrap()).collect();
+            // Clean up test files
+            for zip_path in &zip_files {
+                let _ = fs::remove_file(zip_path);
+            }
+            let _ = fs::remove_file(format!("{}/load_person_table_1000.psql", output_dir));
+            let _ = fs::remove_file(format!("{}/manifest_person_table_1000.json", output_dir));
+            let _ = fs::remove_dir(output_dir);
+            contents
+        };
+
+        // The thread count must not change a single byte
+        let first = write("./output_data/seed_test_a", 42, 1);
+        assert_eq!(first, write("./output_data/seed_test_b", 42, 4));
+        assert_ne!(first, write("./output_data/seed_test_c", 43, 4));
+    }
+
+    #[test]
+    fn test_thread_pool_size() {
+        assert_eq!(super::thread_pool(3).unwrap().install(rayon::current_num_threads), 3);
+    }
+
+    #[test]
+    fn test_write_parquet_splits_in_row_groups() {
+        use polars::prelude::*;
+
+        let output_dir = "./output_data/parquet_test";
+        let mut schema = super::load_schema("./test_data/schema_options.yaml".to_string(), &[]).unwrap();
+        schema.seed = Some(42);
+        super::write_schema_parquet_concurrent(output_dir.to_string(), schema, 1000, 3, 100, 2).unwrap();
+
+        let parquet_files: Vec<String> = (0..3).map(|i| {
+            format!("{}/output_person_table_1000_{:02}.parquet", output_dir, i)
+        }).collect();
+
+        // 333, 333 and 334 rows, in row groups of at most 100 rows
+        let mut total_rows = 0;
+        for (parquet_path, row_groups) in parquet_files.iter().zip([4, 4, 4]) {
+            let mut reader = ParquetReader::new(fs::File::open(parquet_path).unwrap());
+            assert_eq!(reader.get_metadata().unwrap().row_groups.len(), row_groups);
+            let df = reader.finish().unwrap();
+            assert_eq!(df.column("dob").unwrap().dtype(), &DataType::Date);
+            assert_eq!(df.column("event_date").unwrap().dtype(), &DataType::Datetime(TimeUnit::Milliseconds, None));
+            assert_eq!(df.column("event_date").unwrap().null_count(), 0);
+            total_rows += df.height();
+            assert!(!Path::new(&super::resume::temp_path(parquet_path)).exists());
+        }
+        assert_eq!(total_rows, 1000);
+
+        // The manifest lists the splits with their rows, and they match it
+        let manifest_path = format!("{}/manifest_person_table_1000_parquet.json", output_dir);
+        let manifest = super::manifest::read_manifest(Path::new(&manifest_path)).unwrap();
+        assert_eq!(manifest.seed, 42);
+        let dataset = &manifest.datasets[0];
+        assert_eq!(dataset.format, "parquet");
+        assert_eq!(dataset.files.iter().map(|file| (file.name.as_str(), file.rows)).collect::<Vec<_>>(),
+                   vec![("output_person_table_1000_00.parquet", 333), ("output_person_table_1000_01.parquet", 333), ("output_person_table_1000_02.parquet", 334)]);
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+        fs::write(&parquet_files[1], b"PAR1").unwrap();
+        let problems = super::verify_output(output_dir.to_string()).unwrap();
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].starts_with("output_person_table_1000_01.parquet: "));
+
+        // Clean up test files
+        for parquet_path in &parquet_files {
+            let _ = fs::remove_file(parquet_path);
+        }
+        let _ = fs::remove_file(manifest_path);
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_avro_splits() {
+        let output_dir = "./output_data/avro_test";
+        let schema = super::load_schema("./test_data/schema_options.yaml".to_string(), &[]).unwrap();
+        super::write_schema_avro_concurrent(output_dir.to_string(), schema, 1000, 3, super::avro_sink::Codec::Deflate, 2).unwrap();
+
+        let avro_files: Vec<String> = (0..3).map(|i| {
+            format!("{}/output_person_table_1000_{:02}.avro", output_dir, i)
+        }).collect();
+
+        let mut rows = Vec::new();
+        for avro_path in &avro_files {
+            let reader = avro_rs::Reader::new(fs::File::open(avro_path).unwrap()).unwrap();
+            rows.push(reader.collect::<Result<Vec<_>, _>>().unwrap().len());
+        }
+        assert_eq!(rows, vec![333, 333, 334]);
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+
+        // Clean up test files
+        for avro_path in &avro_files {
+            let _ = fs::remove_file(avro_path);
+        }
+        let _ = fs::remove_file(format!("{}/manifest_person_table_1000_avro.json", output_dir));
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_json_splits() {
+        let output_dir = "./output_data/json_test";
+        let schema = super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap();
+        super::write_schema_json_concurrent(output_dir.to_string(), schema, 100, 2, 2, 2, 0, super::Compression::default()).unwrap();
+
+        let zip_files: Vec<String> = (0..2).map(|i| {
+            format!("{}/output_serial_table_100_{:02}.json.zip", output_dir, i)
+        }).collect();
+
+        let mut ids: Vec<i64> = Vec::new();
+        for zip_path in &zip_files {
+            for line in read_zip_entries(zip_path).lines() {
+                let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                ids.push(record["id"].as_i64().unwrap());
+            }
+        }
+        assert_eq!(ids, (1..=100).collect::<Vec<i64>>());
+
+        // The manifest lists the splits inside their archives, and they match it
+        let manifest_path = format!("{}/manifest_serial_table_100_json.json", output_dir);
+        let manifest = super::manifest::read_manifest(Path::new(&manifest_path)).unwrap();
+        let dataset = &manifest.datasets[0];
+        assert_eq!(dataset.format, "json");
+        assert_eq!(dataset.files.iter().map(|file| (file.split, file.rows)).collect::<Vec<_>>(), vec![(0, 50), (1, 50)]);
+        assert_eq!(dataset.archives.iter().map(|archive| archive.name.as_str()).collect::<Vec<_>>(),
+                   vec!["output_serial_table_100_00.json.zip", "output_serial_table_100_01.json.zip"]);
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+
+        // Clean up test files
+        for zip_path in &zip_files {
+            let _ = fs::remove_file(zip_path);
+        }
+        let _ = fs::remove_file(manifest_path);
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_pgcopy_splits() {
+        let output_dir = "./output_data/pgcopy_test";
+        let schema = super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap();
+        super::write_schema_pgcopy_concurrent(output_dir.to_string(), schema, 100, 2, 2, 2, 2).unwrap();
+
+        // Both splits in one ZIP file, each a complete COPY file with header and trailer
+        let zip_path = format!("{}/output_serial_table_100_00_01.pgcopy.zip", output_dir);
+        let mut archive = zip::ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+        assert_eq!(archive.len(), 2);
+        for i in 0..archive.len() {
+            let mut content = Vec::new();
+            archive.by_index(i).unwrap().read_to_end(&mut content).unwrap();
+            assert!(content.starts_with(b"PGCOPY\n\xff\r\n\0"));
+            assert!(content.ends_with(&[0xff, 0xff]));
+        }
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+
+        // Clean up test files
+        let _ = fs::remove_file(&zip_path);
+        let _ = fs::remove_file(format!("{}/manifest_serial_table_100_pgcopy.json", output_dir));
+        let _ = fs::remove_dir(output_dir);
+    }
+
+    #[test]
+    fn test_write_sql_splits() {
+        let output_dir = "./output_data/sql_test";
+        let schema = super::load_schema("./test_data/schema_serial.yaml".to_string(), &[]).unwrap();
+        let options = super::sql_sink::SqlOptions { rows_per_statement: 30, transaction: true, on_conflict_do_nothing: false };
+        super::write_schema_sql_concurrent(output_dir.to_string(), schema, 100, 2, options, 2).unwrap();
+
+        let sql_files: Vec<String> = (0..2).map(|i| {
+            format!("{}/output_serial_table_100_{:02}.sql", output_dir, i)
+        }).collect();
+
+        // 50 rows per split: statements of 30 and 20 rows in one transaction
+        for sql_path in &sql_files {
+            let script = fs::read_to_string(sql_path).unwrap();
+            assert!(script.starts_with("SET standard_conforming_strings = on;\nBEGIN;\n") && script.ends_with("COMMIT;\n"));
+            assert_eq!(script.matches("INSERT INTO \"serial_table\" (\"id\", \"order_id\", \"name\") VALUES").count(), 2);
+            assert_eq!(script.lines().filter(|line| line.starts_with('(')).count(), 50);
+        }
+        assert!(super::verify_output(output_dir.to_string()).unwrap().is_empty());
+
+        // Clean up test files
+        for sql_path in &sql_files {
+            let _ = fs::remove_file(sql_path);
+        }
+        let _ = fs::remove_file(format!("{}/manifest_serial_table_100_sql.json", output_dir));
         let _ = fs::remove_dir(output_dir);
     }
 }
@@ -0,0 +1,93 @@
+use crate::compression::Codec;
+use crate::schema::Schema;
+use crate::sinks::csv_sink::CsvOptions;
+use crate::sinks::sql_sink::{quote_identifier, quote_literal};
+use std::fs;
+use std::io;
+
+/// File written to the output directory and the splits it holds, in split order: a ZIP or tar file, a
+/// compressed split or, without compression, the split itself
+#[derive(Debug, PartialEq, Clone)]
+pub struct Archive {
+    pub file_name: String,
+    pub entries: Vec<String>,
+}
+
+/// One psql `\copy` command per split file, streaming the split out of its file with the decompressor of
+/// `codec`, e.g. `unzip -p`. The column list and the `FORMAT csv` options match what the CSV sink writes
+/// for the dataset with `delimiter` and `csv_options`.
+pub fn copy_commands(schema: &Schema, delimiter: u8, csv_options: &CsvOptions, codec: Codec, archives: &[Archive]) -> Vec<String> {
+    let columns: Vec<String> = schema.dataset.columns.iter().map(|column| quote_identifier(&column.name)).collect();
+    let target = format!("{} ({})", quote_identifier(&schema.dataset.name), columns.join(", "));
+    let literal = |byte: u8| quote_literal(&(byte as char).to_string());
+    let mut options = format!("FORMAT csv, DELIMITER {}", literal(delimiter));
+    if csv_options.header {
+        options.push_str(", HEADER");
+    }
+    if !csv_options.null.is_empty() {
+        options.push_str(&format!(", NULL {}", quote_literal(&csv_options.null)));
+    }
+    if csv_options.quote != b'"' {
+        options.push_str(&format!(", QUOTE {}", literal(csv_options.quote)));
+    }
+    if let Some(escape) = csv_options.escape {
+        options.push_str(&format!(", ESCAPE {}", literal(escape)));
+    }
+    archives.iter()
+        .flat_map(|archive| archive.entries.iter().map(move |entry| (archive, entry)))
+        .map(|(archive, entry)| match codec.program(&archive.file_name, entry) {
+            Some(program) => format!("\\copy {} FROM PROGRAM {} WITH ({})", target, quote_literal(&program), options),
+            None => format!("\\copy {} FROM {} WITH ({})", target, quote_literal(&archive.file_name), options),
+        })
+        .collect()
+}
+
+/// Writes a psql script running the `\copy` commands in order and stopping at the first error.
+/// The ZIP files are opened relative to the working directory, so psql runs from the output directory:
+/// `cd <output_dir> && psql -f <script>`.
+pub fn write_load_script(path: &str, commands: &[String]) -> io::Result<()> {
+    let mut script = String::from("-- Loads the generated files: cd to this directory, then run psql -f on this script\n");
+    script.push_str("\\set ON_ERROR_STOP on\n");
+    for command in commands {
+        script.push_str(command);
+        script.push('\n');
+    }
+    fs::write(path, script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_every_entry_of_every_archive() {
+        let schema = Schema::from_path("./test_data/schema_serial.yaml".to_string()).unwrap();
+        let archives = vec![
+            Archive {
+                file_name: "output_serial_table_10_00_01.csv.zip".to_string(),
+                entries: vec!["output_serial_table_10_00.csv".to_string(), "output_serial_table_10_01.csv".to_string()],
+            },
+            Archive {
+                file_name: "output_serial_table_10_02_02.csv.zip".to_string(),
+                entries: vec!["output_serial_table_10_02.csv".to_string()],
+            },
+        ];
+
+        let commands = copy_commands(&schema, b'|', &CsvOptions::default(), Codec::Zip, &archives);
+        assert_eq!(commands.len(), 3);
+        pretty_assertions::assert_eq!(
+            "\\copy \"serial_table\" (\"id\", \"order_id\", \"name\") FROM PROGRAM 'unzip -p \"output_serial_table_10_02_02.csv.zip\" \"output_serial_table_10_02.csv\"' WITH (FORMAT csv, DELIMITER '|')",
+            commands[2]
+        );
+
+        let archives = vec![Archive {
+            file_name: "output_serial_table_10_00.csv.zst".to_string(),
+            entries: vec!["output_serial_table_10_00.csv".to_string()],
+        }];
+        assert!(copy_commands(&schema, b'|', &CsvOptions::default(), Codec::Zstd, &archives)[0].contains("FROM PROGRAM 'zstd -dc \"output_serial_table_10_00.csv.zst\"'"));
+        let archives = vec![Archive { file_name: "output_serial_table_10_00.csv".to_string(), entries: archives[0].entries.clone() }];
+        let csv_options = CsvOptions { header: true, null: "\\N".to_string(), escape: Some(b'\\'), ..CsvOptions::default() };
+        assert!(copy_commands(&schema, b'|', &csv_options, Codec::None, &archives)[0]
+            .ends_with("FROM 'output_serial_table_10_00.csv' WITH (FORMAT csv, DELIMITER '|', HEADER, NULL '\\N', ESCAPE '\\')"));
+    }
+}
@@ -2,19 +2,20 @@ extern crate structopt;
 
 use std::fmt::Error;
 use std::fs;
-use std::fs::File;
 
 use failure_tools::ok_or_exit;
 use structopt::StructOpt;
 
-use datagen::{write_csv_concurrent};
-use std::path::Path;
+use datagen::{load_schema, schema_ddl, verify_output, write_multi_schema_csv_concurrent, write_schema_avro_concurrent, write_schema_csv_concurrent, write_schema_json_concurrent, write_schema_parquet_concurrent, write_schema_pgcopy_concurrent, write_schema_sql_concurrent};
+use datagen::errors::DataGenResult;
+use datagen::options;
+use datagen::schema::Schema;
+use datagen::sinks::csv_sink::CsvOptions;
+use datagen::sinks::sql_sink::SqlOptions;
 use num_format::{Locale, ToFormattedString};
 use chrono::Utc;
 use std::time::Instant;
 
-mod options;
-
 fn run() -> Result<(), Error> {
     use options::Command::*;
 
@@ -28,43 +29,285 @@ fn run() -> Result<(), Error> {
             delimiter,
             file_splits,
             thread_pool_size,
+            zip_thread_pool_size,
             zip_pack_batch_size,
+            compression,
+            compression_level,
+            csv_dialect,
+            parent_schema_paths,
+            seed,
         } => {
-
-            let custom_format = Utc::now().format("%Y-%m-%d %H:%M:%S.%3f UTC");
-            println!("Time start: {}", custom_format);
-            // Compute effective batch size
-            let effective_batch_size = if zip_pack_batch_size == 0 { file_splits } else { zip_pack_batch_size };
-            
-            println!(
-                "Output Path:{}, Schema Path:{}, Total Records:{}, CSV files:{}, ThreadPool Size:{}, Zip Batch Size:{}",
+            let compression = options::compression(compression, compression_level);
+            let zip_thread_pool_size = zip_thread_pool_size.unwrap_or(thread_pool_size);
+            timed(format!(
+                "Output Path:{}, Schema Path:{}, Total Records:{}, CSV files:{}, ThreadPool Size:{}, Zip ThreadPool Size:{}, Zip Batch Size:{}",
                 &output_path, schema_path, num_records.to_formatted_string(&Locale::fr),
                 file_splits.to_formatted_string(&Locale::fr),
                 thread_pool_size,
-                effective_batch_size.to_formatted_string(&Locale::fr)
-            );
-            let start_time = Instant::now();
-
-            let _ = fs::create_dir_all(&output_path);
-            write_csv_concurrent(
-                output_path,
-                schema_path,
-                num_records as i64,
-                delimiter,
-                file_splits,
+                zip_thread_pool_size,
+                zip_pack_batch_size.to_formatted_string(&Locale::fr)
+            ), output_path, |output_path| {
+                let schema = load_seeded_schema(schema_path, &parent_schema_paths, seed);
+                or_exit(write_schema_csv_concurrent(
+                    output_path,
+                    schema,
+                    num_records as i64,
+                    delimiter,
+                    file_splits,
+                    thread_pool_size,
+                    zip_thread_pool_size,
+                    zip_pack_batch_size,
+                    csv_options(csv_dialect),
+                    compression,
+                ), "Failed to write concurrent CSV files");
+            });
+        }
+        GenerateParquet {
+            output_path,
+            schema_path,
+            num_records,
+            file_splits,
+            thread_pool_size,
+            row_group_size,
+            parent_schema_paths,
+            seed,
+        } => {
+            timed(format!(
+                "Output Path:{}, Schema Path:{}, Total Records:{}, Parquet files:{}, ThreadPool Size:{}, Row Group Size:{}",
+                &output_path, schema_path, num_records.to_formatted_string(&Locale::fr),
+                file_splits.to_formatted_string(&Locale::fr),
                 thread_pool_size,
-                effective_batch_size,  // New parameter
-            ).expect("Failed to write concurrent CSV files");
-
-            println!("Time finish: {}", Utc::now().format("%Y-%m-%d %H:%M:%S.%3f UTC"));
-            println!("Time spent: {:.3} minutes", (start_time.elapsed().as_secs_f64()/60.0));
-
+                row_group_size.to_formatted_string(&Locale::fr)
+            ), output_path, |output_path| {
+                let schema = load_seeded_schema(schema_path, &parent_schema_paths, seed);
+                or_exit(write_schema_parquet_concurrent(
+                    output_path,
+                    schema,
+                    num_records as i64,
+                    file_splits,
+                    row_group_size,
+                    thread_pool_size,
+                ), "Failed to write concurrent Parquet files");
+            });
+        }
+        GenerateAvro {
+            output_path,
+            schema_path,
+            num_records,
+            file_splits,
+            thread_pool_size,
+            codec,
+            parent_schema_paths,
+            seed,
+        } => {
+            timed(format!(
+                "Output Path:{}, Schema Path:{}, Total Records:{}, Avro files:{}, ThreadPool Size:{}, Codec:{}",
+                &output_path, schema_path, num_records.to_formatted_string(&Locale::fr),
+                file_splits.to_formatted_string(&Locale::fr),
+                thread_pool_size,
+                codec.name()
+            ), output_path, |output_path| {
+                let schema = load_seeded_schema(schema_path, &parent_schema_paths, seed);
+                or_exit(write_schema_avro_concurrent(
+                    output_path,
+                    schema,
+                    num_records as i64,
+                    file_splits,
+                    codec,
+                    thread_pool_size,
+                ), "Failed to write concurrent Avro files");
+            });
+        }
+        GenerateJson {
+            output_path,
+            schema_path,
+            num_records,
+            file_splits,
+            thread_pool_size,
+            zip_pack_batch_size,
+            zip_thread_pool_size,
+            compression,
+            compression_level,
+            parent_schema_paths,
+            seed,
+        } => {
+            let compression = options::compression(compression, compression_level);
+            let zip_thread_pool_size = zip_thread_pool_size.unwrap_or(thread_pool_size);
+            timed(format!(
+                "Output Path:{}, Schema Path:{}, Total Records:{}, JSON files:{}, ThreadPool Size:{}, Zip ThreadPool Size:{}, Zip Batch Size:{}",
+                &output_path, schema_path, num_records.to_formatted_string(&Locale::fr),
+                file_splits.to_formatted_string(&Locale::fr),
+                thread_pool_size,
+                zip_thread_pool_size,
+                zip_pack_batch_size.to_formatted_string(&Locale::fr)
+            ), output_path, |output_path| {
+                let schema = load_seeded_schema(schema_path, &parent_schema_paths, seed);
+                or_exit(write_schema_json_concurrent(
+                    output_path,
+                    schema,
+                    num_records as i64,
+                    file_splits,
+                    thread_pool_size,
+                    zip_thread_pool_size,
+                    zip_pack_batch_size,
+                    compression,
+                ), "Failed to write concurrent JSON files");
+            });
+        }
+        GeneratePgCopy {
+            output_path,
+            schema_path,
+            num_records,
+            file_splits,
+            thread_pool_size,
+            zip_pack_batch_size,
+            zip_thread_pool_size,
+            parent_schema_paths,
+            seed,
+        } => {
+            let zip_thread_pool_size = zip_thread_pool_size.unwrap_or(thread_pool_size);
+            timed(format!(
+                "Output Path:{}, Schema Path:{}, Total Records:{}, COPY files:{}, ThreadPool Size:{}, Zip ThreadPool Size:{}, Zip Batch Size:{}",
+                &output_path, schema_path, num_records.to_formatted_string(&Locale::fr),
+                file_splits.to_formatted_string(&Locale::fr),
+                thread_pool_size,
+                zip_thread_pool_size,
+                zip_pack_batch_size.to_formatted_string(&Locale::fr)
+            ), output_path, |output_path| {
+                let schema = load_seeded_schema(schema_path, &parent_schema_paths, seed);
+                or_exit(write_schema_pgcopy_concurrent(
+                    output_path,
+                    schema,
+                    num_records as i64,
+                    file_splits,
+                    thread_pool_size,
+                    zip_thread_pool_size,
+                    zip_pack_batch_size,
+                ), "Failed to write concurrent COPY files");
+            });
+        }
+        GenerateSql {
+            output_path,
+            schema_path,
+            num_records,
+            file_splits,
+            thread_pool_size,
+            rows_per_statement,
+            transaction,
+            on_conflict_do_nothing,
+            parent_schema_paths,
+            seed,
+        } => {
+            timed(format!(
+                "Output Path:{}, Schema Path:{}, Total Records:{}, SQL files:{}, ThreadPool Size:{}, Rows per Statement:{}",
+                &output_path, schema_path, num_records.to_formatted_string(&Locale::fr),
+                file_splits.to_formatted_string(&Locale::fr),
+                thread_pool_size,
+                rows_per_statement.to_formatted_string(&Locale::fr)
+            ), output_path, |output_path| {
+                let schema = load_seeded_schema(schema_path, &parent_schema_paths, seed);
+                or_exit(write_schema_sql_concurrent(
+                    output_path,
+                    schema,
+                    num_records as i64,
+                    file_splits,
+                    SqlOptions { rows_per_statement, transaction, on_conflict_do_nothing },
+                    thread_pool_size,
+                ), "Failed to write concurrent SQL files");
+            });
+        }
+        GenerateAll {
+            output_path,
+            schema_path,
+            thread_pool_size,
+            zip_thread_pool_size,
+            zip_pack_batch_size,
+            compression,
+            compression_level,
+            csv_dialect,
+            seed,
+        } => {
+            let compression = options::compression(compression, compression_level);
+            let zip_thread_pool_size = zip_thread_pool_size.unwrap_or(thread_pool_size);
+            timed(format!(
+                "Output Path:{}, Schema Path:{}, ThreadPool Size:{}, Zip ThreadPool Size:{}",
+                &output_path, schema_path, thread_pool_size, zip_thread_pool_size
+            ), output_path, |output_path| {
+                or_exit(write_multi_schema_csv_concurrent(
+                    output_path,
+                    schema_path,
+                    seed,
+                    thread_pool_size,
+                    zip_thread_pool_size,
+                    zip_pack_batch_size,
+                    csv_options(csv_dialect),
+                    compression,
+                ), "Failed to generate the datasets");
+            });
+        }
+        GenerateDdl { schema_path, keys } => {
+            // Only the statements go to stdout, so the output can be piped into psql
+            print!("{}", or_exit(schema_ddl(schema_path, keys), "Failed to generate the DDL"));
+        }
+        Verify { output_path } => {
+            let problems = or_exit(verify_output(output_path.clone()), "Failed to verify the output");
+            if !problems.is_empty() {
+                for problem in &problems {
+                    eprintln!("{}", problem);
+                }
+                eprintln!("{} problem(s) found in {}", problems.len(), output_path);
+                std::process::exit(1);
+            }
+            println!("All files in {} match their manifest", output_path);
         }
     }
 
     Ok(())
 }
 
+fn csv_options(dialect: options::CsvDialect) -> CsvOptions {
+    CsvOptions {
+        header: dialect.header,
+        quote_style: dialect.quote_style,
+        quote: dialect.quote,
+        escape: dialect.escape,
+        terminator: dialect.terminator,
+        null: dialect.null.unwrap_or_default(),
+        postgres: dialect.postgres,
+    }
+}
+
+/// Loads the schema, with `seed` overriding the one it declares
+fn load_seeded_schema(schema_path: String, parent_schema_paths: &[String], seed: Option<u64>) -> Schema {
+    let mut schema = or_exit(load_schema(schema_path, parent_schema_paths), "Failed to load the schema");
+    if seed.is_some() {
+        schema.seed = seed;
+    }
+    schema
+}
+
+/// Prints the run settings and start time, creates the output directory, then runs `generate` on it
+/// and prints the finish time and time spent
+fn timed<F: FnOnce(String)>(settings: String, output_path: String, generate: F) {
+    println!("Time start: {}", Utc::now().format("%Y-%m-%d %H:%M:%S.%3f UTC"));
+    println!("{}", settings);
+    let start_time = Instant::now();
+
+    let _ = fs::create_dir_all(&output_path);
+    generate(output_path);
+
+    println!("Time finish: {}", Utc::now().format("%Y-%m-%d %H:%M:%S.%3f UTC"));
+    println!("Time spent: {:.3} minutes", (start_time.elapsed().as_secs_f64()/60.0));
+}
+
+/// Exits with the error's message, e.g. every problem of an invalid schema, rather than its debug form
+fn or_exit<T>(result: DataGenResult<T>, context: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}: {}", context, e);
+        std::process::exit(1);
+    })
+}
+
 fn main() {
     ok_or_exit(run())
 }
@@ -0,0 +1,324 @@
+use crate::compression::{self, Codec};
+use crate::errors::DataGenError::WeirdCase;
+use crate::errors::*;
+use crate::load_script::Archive;
+use crate::sinks::csv_sink::CsvOptions;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f UTC";
+
+/// Record of a run, written as JSON next to its output: what was generated, from which schema and
+/// with which settings, and the size, row count and SHA-256 of every file, so a directory can be
+/// checked with `verify_output` after an interrupted or copied run.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Manifest {
+    /// SHA-256 of the schema definition, seed excluded
+    pub schema_sha256: String,
+    pub seed: u64,
+    /// Command line of the process that generated the files
+    pub arguments: Vec<String>,
+    pub started_at: String,
+    pub finished_at: String,
+    pub elapsed_seconds: f64,
+    pub datasets: Vec<DataSetManifest>,
+}
+
+impl Manifest {
+    /// Manifest of a run started at `started_at` and finishing now, with the process arguments
+    pub fn new(schema_sha256: String, seed: u64, started_at: DateTime<Utc>, datasets: Vec<DataSetManifest>) -> Manifest {
+        let finished_at = Utc::now();
+        Manifest {
+            schema_sha256,
+            seed,
+            arguments: std::env::args().collect(),
+            started_at: started_at.format(TIME_FORMAT).to_string(),
+            finished_at: finished_at.format(TIME_FORMAT).to_string(),
+            elapsed_seconds: (finished_at - started_at).num_milliseconds() as f64 / 1000.0,
+            datasets,
+        }
+    }
+}
+
+/// Files of a dataset. The CSV dialect is only recorded for CSV splits, whose rows `verify` counts back.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct DataSetManifest {
+    pub name: String,
+    /// Row count in the file names; the owner row count for join tables
+    pub num_records: i64,
+    /// Format of the splits: csv, json, pgcopy, avro, sql or parquet
+    #[serde(default = "csv_format")]
+    pub format: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    /// Whether every split starts with a header row, which is not counted in `rows`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<String>,
+    /// Character escaping quotes inside quoted fields, quotes being doubled without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escape: Option<String>,
+    /// Codec of the files holding the splits, see `Codec`
+    pub compression: String,
+    /// Splits, in split order
+    pub files: Vec<FileManifest>,
+    /// Files holding the splits: ZIP or tar files, compressed splits or the splits themselves
+    pub archives: Vec<ArchiveManifest>,
+}
+
+fn csv_format() -> String {
+    "csv".to_string()
+}
+
+impl DataSetManifest {
+    /// Manifest of a dataset written in another format than CSV, `archives` holding its splits or being
+    /// empty when the splits are written straight into `output_dir`
+    pub fn new(name: &str, num_records: i64, format: &str, compression: Codec, files: Vec<FileManifest>, archives: Vec<ArchiveManifest>) -> DataSetManifest {
+        DataSetManifest {
+            name: name.to_string(),
+            num_records,
+            format: format.to_string(),
+            delimiter: None,
+            header: None,
+            quote: None,
+            escape: None,
+            compression: compression.name().to_string(),
+            files,
+            archives,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct FileManifest {
+    pub name: String,
+    pub split: usize,
+    pub rows: u64,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ArchiveManifest {
+    pub name: String,
+    pub bytes: u64,
+    pub sha256: String,
+    /// Names of the splits the archive holds
+    pub entries: Vec<String>,
+}
+
+/// Hashes and counts everything read through it
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.bytes += read as u64;
+        Ok(read)
+    }
+}
+
+/// Hashes and counts everything written through it, so a split's manifest is known as soon as it is written
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> HashingWriter<W> {
+        HashingWriter { inner, hasher: Sha256::new(), bytes: 0 }
+    }
+
+    /// Size and SHA-256 of what was written
+    pub fn finish(self) -> (u64, String) {
+        (self.bytes, hex(&self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Size, SHA-256 and CSV record count of a split read from `reader` with the dialect of `csv`
+pub fn file_manifest<R: Read>(name: &str, split: usize, reader: R, csv: &csv::ReaderBuilder) -> DataGenResult<FileManifest> {
+    let hashing = HashingReader { inner: reader, hasher: Sha256::new(), bytes: 0 };
+    let mut csv_reader = csv.from_reader(hashing);
+    let mut rows = 0;
+    for record in csv_reader.byte_records() {
+        record?;
+        rows += 1;
+    }
+    let hashing = csv_reader.into_inner();
+    Ok(FileManifest { name: name.to_string(), split, rows, bytes: hashing.bytes, sha256: hex(&hashing.hasher.finalize()) })
+}
+
+/// Size and SHA-256 of a split whose rows are not counted back, e.g. a Parquet file, with the row count
+/// it was written with
+pub fn written_file_manifest<R: Read>(name: &str, split: usize, rows: u64, reader: R) -> DataGenResult<FileManifest> {
+    let (bytes, sha256) = hash(reader)?;
+    Ok(FileManifest { name: name.to_string(), split, rows, bytes, sha256 })
+}
+
+/// Size and SHA-256 of a file holding splits in `output_dir`
+pub fn archive_manifest(output_dir: &str, archive: &Archive) -> DataGenResult<ArchiveManifest> {
+    let (bytes, sha256) = hash(fs::File::open(Path::new(output_dir).join(&archive.file_name))?)?;
+    Ok(ArchiveManifest {
+        name: archive.file_name.clone(),
+        bytes,
+        sha256,
+        entries: archive.entries.clone(),
+    })
+}
+
+fn hash<R: Read>(mut reader: R) -> DataGenResult<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let bytes = io::copy(&mut reader, &mut hasher)?;
+    Ok((bytes, hex(&hasher.finalize())))
+}
+
+pub fn write_manifest(path: &str, manifest: &Manifest) -> DataGenResult<()> {
+    let json = serde_json::to_string_pretty(manifest).map_err(|e| WeirdCase {
+        message: format!("Failed to serialize the manifest {}: {}", path, e)
+    })?;
+    fs::write(path, json + "\n")?;
+    Ok(())
+}
+
+pub fn read_manifest(path: &Path) -> DataGenResult<Manifest> {
+    serde_json::from_reader(fs::File::open(path)?).map_err(|e| WeirdCase {
+        message: format!("Failed to read the manifest {}: {}", path.display(), e)
+    })
+}
+
+/// Checks the files of a manifest against `output_dir` and returns every mismatch. Each file holding splits
+/// must match its size and SHA-256, and each split must match its size, SHA-256 and, for CSV splits, row
+/// count, read from the file holding it, or from the directory when no file holds it.
+pub fn verify(output_dir: &str, manifest: &Manifest) -> DataGenResult<Vec<String>> {
+    let mut problems = Vec::new();
+    for dataset in &manifest.datasets {
+        let first_byte = |text: &str| text.as_bytes().first().copied();
+        let csv = (dataset.format == "csv").then(|| {
+            let csv_options = CsvOptions {
+                header: dataset.header.unwrap_or_default(),
+                quote: dataset.quote.as_deref().and_then(first_byte).unwrap_or(b'"'),
+                escape: dataset.escape.as_deref().and_then(first_byte),
+                ..CsvOptions::default()
+            };
+            csv_options.reader(dataset.delimiter.as_deref().and_then(first_byte).unwrap_or(b','))
+        });
+        let codec: Codec = dataset.compression.parse()?;
+        let mut verified: Vec<&str> = Vec::new();
+        for expected in &dataset.archives {
+            // Splits of a missing or broken file are reported through the file
+            verified.extend(expected.entries.iter().map(String::as_str));
+            let path = Path::new(output_dir).join(&expected.name);
+            if !path.exists() {
+                problems.push(format!("{}: missing", expected.name));
+                continue;
+            }
+            let archive = Archive { file_name: expected.name.clone(), entries: expected.entries.clone() };
+            let actual = archive_manifest(output_dir, &archive)?;
+            if actual.bytes != expected.bytes || actual.sha256 != expected.sha256 {
+                problems.push(format!("{}: {} bytes with SHA-256 {}, expected {} bytes with SHA-256 {}",
+                                      expected.name, actual.bytes, actual.sha256, expected.bytes, expected.sha256));
+            }
+            let first_entry = match expected.entries.first() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let mut found: Vec<String> = Vec::new();
+            let mut entry_problems = Vec::new();
+            let read = compression::read_file(&path, codec, first_entry, |name, reader| {
+                let file = dataset.files.iter()
+                    .find(|file| file.name == name && expected.entries.contains(&file.name));
+                if let Some(file) = file {
+                    let location = if codec.is_container() { format!("{}/{}", expected.name, name) } else { expected.name.clone() };
+                    check_file(&mut entry_problems, &location, file, reader, csv.as_ref());
+                    found.push(name.to_string());
+                }
+                Ok(())
+            });
+            problems.extend(entry_problems);
+            if let Err(e) = read {
+                problems.push(format!("{}: unreadable {} file: {}", expected.name, codec.name(), e));
+                continue;
+            }
+            for entry in expected.entries.iter().filter(|entry| !found.contains(entry)) {
+                if dataset.files.iter().any(|file| &file.name == entry) {
+                    problems.push(format!("{}/{}: missing", expected.name, entry));
+                }
+            }
+        }
+        for file in dataset.files.iter().filter(|file| !verified.contains(&file.name.as_str())) {
+            match fs::File::open(Path::new(output_dir).join(&file.name)) {
+                Ok(reader) => check_file(&mut problems, &file.name, file, reader, csv.as_ref()),
+                Err(_) => problems.push(format!("{}: missing", file.name)),
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Checks a split, counting its rows back with the reader of CSV splits
+fn check_file<R: Read>(problems: &mut Vec<String>, location: &str, expected: &FileManifest, reader: R, csv: Option<&csv::ReaderBuilder>) {
+    let actual = match csv {
+        Some(csv) => file_manifest(&expected.name, expected.split, reader, csv),
+        None => written_file_manifest(&expected.name, expected.split, expected.rows, reader),
+    };
+    match actual {
+        Ok(actual) if actual != *expected => problems.push(format!(
+            "{}: {} rows, {} bytes with SHA-256 {}, expected {} rows, {} bytes with SHA-256 {}",
+            location, actual.rows, actual.bytes, actual.sha256, expected.rows, expected.bytes, expected.sha256
+        )),
+        Ok(_) => {}
+        Err(e) => problems.push(format!("{}: unreadable: {}", location, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_rows_and_hash_a_split() {
+        let csv = "1|\"Jason\"\n2|\"multi\nline\"\n3|\"Anna\"\n";
+        let file = file_manifest("output_t_3_00.csv", 0, csv.as_bytes(), &CsvOptions::default().reader(b'|')).unwrap();
+        assert_eq!(file.rows, 3);
+        assert_eq!(file.bytes, csv.len() as u64);
+        assert_eq!(file.sha256, sha256_hex(csv.as_bytes()));
+        let with_header = CsvOptions { header: true, ..CsvOptions::default() }.reader(b'|');
+        assert_eq!(file_manifest("output_t_3_00.csv", 0, csv.as_bytes(), &with_header).unwrap().rows, 2);
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        let mut writer = HashingWriter::new(Vec::new());
+        writer.write_all(csv.as_bytes()).unwrap();
+        assert_eq!(writer.finish(), (file.bytes, file.sha256));
+    }
+}
@@ -1,5 +1,8 @@
+use crate::compression::{Codec, Compression};
+use crate::sinks::avro_sink;
+use crate::sinks::csv_sink::{QuoteStyle, Terminator};
+use structopt::clap;
 use structopt::StructOpt;
-use std::num::ParseIntError;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -11,8 +14,51 @@ pub struct Args {
     pub command: Command,
 }
 
-fn convert_to_u8(src: &str) -> Result<u8, ParseIntError> {
-    Ok(src.as_bytes()[0])
+/// The one byte of a delimiter, quote or escape argument
+fn convert_to_u8(src: &str) -> Result<u8, String> {
+    match src.as_bytes() {
+        [byte] => Ok(*byte),
+        [] => Err("expected one character, got an empty string".to_string()),
+        _ => Err(format!("expected one single-byte character, got {:?}", src)),
+    }
+}
+
+/// Compression of the codec at the level, exiting with a usage error when the codec takes no such level
+pub fn compression(codec: Codec, level: Option<i32>) -> Compression {
+    Compression::new(codec, level).unwrap_or_else(|e| {
+        clap::Error::with_description(&e.to_string(), clap::ErrorKind::InvalidValue).exit()
+    })
+}
+
+/// Dialect of the CSV files
+#[derive(Debug, StructOpt)]
+pub struct CsvDialect {
+    /// Writes the column names as the first row of every file
+    #[structopt(long = "header")]
+    pub header: bool,
+
+    /// Fields written between quotes: always, necessary, non-numeric or never
+    #[structopt(long = "quote-style", default_value = "non-numeric")]
+    pub quote_style: QuoteStyle,
+
+    #[structopt(long = "quote", default_value = "\"", parse(try_from_str = "convert_to_u8"))]
+    pub quote: u8,
+
+    /// Escapes quotes inside quoted fields with this character instead of doubling them
+    #[structopt(long = "escape", parse(try_from_str = "convert_to_u8"))]
+    pub escape: Option<u8>,
+
+    /// Line terminator: lf or crlf
+    #[structopt(long = "terminator", default_value = "lf")]
+    pub terminator: Terminator,
+
+    /// Written for NULL values, e.g. \N; empty by default
+    #[structopt(long = "null")]
+    pub null: Option<String>,
+
+    /// Writes every value the way Postgres COPY ... WITH (FORMAT csv) reads it back, whatever the quote style
+    #[structopt(long = "postgres")]
+    pub postgres: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -37,8 +83,274 @@ pub enum Command {
         #[structopt(name = "threads", alias = "t", default_value = "32")]
         thread_pool_size: usize,
 
+        /// Threads zipping the CSV files, same as the generation threads by default
+        #[structopt(long = "zip-threads")]
+        zip_thread_pool_size: Option<usize>,
+
         #[structopt(name = "batch", alias = "b", default_value = "0")]
         zip_pack_batch_size: usize,
+
+        /// Compression of the output files: none, gzip, zstd, lz4, zip, zip-stored or tar
+        #[structopt(long = "compression", default_value = "zip")]
+        compression: Codec,
+
+        /// Compression level: 0-9 for gzip and zip, 1-22 for zstd, 0-12 for lz4; the codec's default when unset
+        #[structopt(long = "compression-level")]
+        compression_level: Option<i32>,
+
+        #[structopt(flatten)]
+        csv_dialect: CsvDialect,
+
+        /// Schema of a dataset referenced by this schema's foreign keys (repeatable)
+        #[structopt(long = "parent", number_of_values = 1)]
+        parent_schema_paths: Vec<String>,
+
+        /// Seed of the generated values, overriding the schema's seed; random when neither is set
+        #[structopt(long = "seed")]
+        seed: Option<u64>,
+    },
+
+    /// Generates split Parquet files written in bounded row groups
+    #[structopt(name = "parquet", alias = "p")]
+    GenerateParquet {
+        #[structopt(name = "output", alias = "o")]
+        output_path: String,
+
+        #[structopt(name = "schema", alias = "s")]
+        schema_path: String,
+
+        #[structopt(name = "numrecs", alias = "n")]
+        num_records: usize,
+
+        #[structopt(name = "splits", alias = "sp", default_value = "1")]
+        file_splits: usize,
+
+        #[structopt(name = "threads", alias = "t", default_value = "32")]
+        thread_pool_size: usize,
+
+        /// Rows per Parquet row group, which bounds the rows held in memory per split
+        #[structopt(long = "row-group-size", default_value = "100000")]
+        row_group_size: usize,
+
+        /// Schema of a dataset referenced by this schema's foreign keys (repeatable)
+        #[structopt(long = "parent", number_of_values = 1)]
+        parent_schema_paths: Vec<String>,
+
+        /// Seed of the generated values, overriding the schema's seed; random when neither is set
+        #[structopt(long = "seed")]
+        seed: Option<u64>,
+    },
+
+    /// Generates split Avro object container files
+    #[structopt(name = "avro", alias = "a")]
+    GenerateAvro {
+        #[structopt(name = "output", alias = "o")]
+        output_path: String,
+
+        #[structopt(name = "schema", alias = "s")]
+        schema_path: String,
+
+        #[structopt(name = "numrecs", alias = "n")]
+        num_records: usize,
+
+        #[structopt(name = "splits", alias = "sp", default_value = "1")]
+        file_splits: usize,
+
+        #[structopt(name = "threads", alias = "t", default_value = "32")]
+        thread_pool_size: usize,
+
+        /// Block compression codec: null, deflate or snappy
+        #[structopt(long = "codec", default_value = "snappy")]
+        codec: avro_sink::Codec,
+
+        /// Schema of a dataset referenced by this schema's foreign keys (repeatable)
+        #[structopt(long = "parent", number_of_values = 1)]
+        parent_schema_paths: Vec<String>,
+
+        /// Seed of the generated values, overriding the schema's seed; random when neither is set
+        #[structopt(long = "seed")]
+        seed: Option<u64>,
     },
 
+    /// Generates split NDJSON files, one JSON object per line, packed into ZIP files
+    #[structopt(name = "json", alias = "j")]
+    GenerateJson {
+        #[structopt(name = "output", alias = "o")]
+        output_path: String,
+
+        #[structopt(name = "schema", alias = "s")]
+        schema_path: String,
+
+        #[structopt(name = "numrecs", alias = "n")]
+        num_records: usize,
+
+        #[structopt(name = "splits", alias = "sp", default_value = "1")]
+        file_splits: usize,
+
+        #[structopt(name = "threads", alias = "t", default_value = "32")]
+        thread_pool_size: usize,
+
+        #[structopt(name = "batch", alias = "b", default_value = "0")]
+        zip_pack_batch_size: usize,
+
+        /// Threads zipping the JSON files, same as the generation threads by default
+        #[structopt(long = "zip-threads")]
+        zip_thread_pool_size: Option<usize>,
+
+        /// Compression of the output files: none, gzip, zstd, lz4, zip, zip-stored or tar
+        #[structopt(long = "compression", default_value = "zip")]
+        compression: Codec,
+
+        /// Compression level: 0-9 for gzip and zip, 1-22 for zstd, 0-12 for lz4; the codec's default when unset
+        #[structopt(long = "compression-level")]
+        compression_level: Option<i32>,
+
+        /// Schema of a dataset referenced by this schema's foreign keys (repeatable)
+        #[structopt(long = "parent", number_of_values = 1)]
+        parent_schema_paths: Vec<String>,
+
+        /// Seed of the generated values, overriding the schema's seed; random when neither is set
+        #[structopt(long = "seed")]
+        seed: Option<u64>,
+    },
+
+    /// Generates split PostgreSQL binary COPY files, packed into ZIP files
+    #[structopt(name = "pgcopy", alias = "pg")]
+    GeneratePgCopy {
+        #[structopt(name = "output", alias = "o")]
+        output_path: String,
+
+        #[structopt(name = "schema", alias = "s")]
+        schema_path: String,
+
+        #[structopt(name = "numrecs", alias = "n")]
+        num_records: usize,
+
+        #[structopt(name = "splits", alias = "sp", default_value = "1")]
+        file_splits: usize,
+
+        #[structopt(name = "threads", alias = "t", default_value = "32")]
+        thread_pool_size: usize,
+
+        #[structopt(name = "batch", alias = "b", default_value = "0")]
+        zip_pack_batch_size: usize,
+
+        /// Threads zipping the COPY files, same as the generation threads by default
+        #[structopt(long = "zip-threads")]
+        zip_thread_pool_size: Option<usize>,
+
+        /// Schema of a dataset referenced by this schema's foreign keys (repeatable)
+        #[structopt(long = "parent", number_of_values = 1)]
+        parent_schema_paths: Vec<String>,
+
+        /// Seed of the generated values, overriding the schema's seed; random when neither is set
+        #[structopt(long = "seed")]
+        seed: Option<u64>,
+    },
+
+    /// Generates split SQL scripts of multi-row INSERT statements, loadable with `psql -f`
+    #[structopt(name = "sql")]
+    GenerateSql {
+        #[structopt(name = "output", alias = "o")]
+        output_path: String,
+
+        #[structopt(name = "schema", alias = "s")]
+        schema_path: String,
+
+        #[structopt(name = "numrecs", alias = "n")]
+        num_records: usize,
+
+        #[structopt(name = "splits", alias = "sp", default_value = "1")]
+        file_splits: usize,
+
+        #[structopt(name = "threads", alias = "t", default_value = "32")]
+        thread_pool_size: usize,
+
+        /// Rows per INSERT statement
+        #[structopt(long = "rows-per-statement", default_value = "1000")]
+        rows_per_statement: usize,
+
+        /// Wraps every script in BEGIN and COMMIT
+        #[structopt(long = "transaction")]
+        transaction: bool,
+
+        /// Appends ON CONFLICT DO NOTHING to every statement
+        #[structopt(long = "on-conflict-do-nothing")]
+        on_conflict_do_nothing: bool,
+
+        /// Schema of a dataset referenced by this schema's foreign keys (repeatable)
+        #[structopt(long = "parent", number_of_values = 1)]
+        parent_schema_paths: Vec<String>,
+
+        /// Seed of the generated values, overriding the schema's seed; random when neither is set
+        #[structopt(long = "seed")]
+        seed: Option<u64>,
+    },
+
+    /// Generates every dataset of a multi-dataset schema file in foreign-key dependency order
+    #[structopt(name = "generate", alias = "g")]
+    GenerateAll {
+        #[structopt(name = "output", alias = "o")]
+        output_path: String,
+
+        #[structopt(name = "schema", alias = "s")]
+        schema_path: String,
+
+        #[structopt(name = "threads", alias = "t", default_value = "32")]
+        thread_pool_size: usize,
+
+        /// Threads zipping the CSV files, same as the generation threads by default
+        #[structopt(long = "zip-threads")]
+        zip_thread_pool_size: Option<usize>,
+
+        #[structopt(name = "batch", alias = "b", default_value = "0")]
+        zip_pack_batch_size: usize,
+
+        /// Compression of the output files: none, gzip, zstd, lz4, zip, zip-stored or tar
+        #[structopt(long = "compression", default_value = "zip")]
+        compression: Codec,
+
+        /// Compression level: 0-9 for gzip and zip, 1-22 for zstd, 0-12 for lz4; the codec's default when unset
+        #[structopt(long = "compression-level")]
+        compression_level: Option<i32>,
+
+        #[structopt(flatten)]
+        csv_dialect: CsvDialect,
+
+        /// Seed of the generated values, overriding the schema's seed; random when neither is set
+        #[structopt(long = "seed")]
+        seed: Option<u64>,
+    },
+
+    /// Prints the PostgreSQL CREATE TABLE statements of every dataset of a schema file
+    #[structopt(name = "ddl")]
+    GenerateDdl {
+        #[structopt(name = "schema", alias = "s")]
+        schema_path: String,
+
+        /// Declares the primary keys and indexes marked in the schema
+        #[structopt(long = "keys")]
+        keys: bool,
+    },
+
+    /// Checks the files of an output directory against the manifests written next to them
+    #[structopt(name = "verify")]
+    Verify {
+        #[structopt(name = "output", alias = "o")]
+        output_path: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_one_byte_arguments() {
+        assert_eq!(convert_to_u8("|"), Ok(b'|'));
+        assert_eq!(convert_to_u8("\t"), Ok(b'\t'));
+        assert!(convert_to_u8("").is_err());
+        assert!(convert_to_u8(",,").is_err());
+        assert!(convert_to_u8("é").is_err());
+    }
 }
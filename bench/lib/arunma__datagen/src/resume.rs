@@ -0,0 +1,101 @@
+use crate::errors::DataGenError::WeirdCase;
+use crate::errors::*;
+use crate::sinks::csv_sink::CsvOptions;
+use std::fs;
+
+/// Settings of a dataset run, kept in `output_<table>_<num_records>.resume` until the run completes.
+/// A run finding the file of an interrupted run with the same schema and settings keeps the splits that run
+/// completed, as regenerating them would give the same bytes.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ResumeState {
+    /// SHA-256 of the schema, the one the run's manifest records
+    pub schema_sha256: String,
+    pub seed: u64,
+    pub file_splits: usize,
+    pub zip_pack_batch_size: usize,
+    pub delimiter: String,
+    pub csv_options: CsvOptions,
+    /// Codec and level of the output files, see `Compression`
+    pub compression: String,
+}
+
+pub fn state_path(output_dir: &str, table_name: &str, num_records: i64) -> String {
+    format!("{}/output_{}_{}.resume", output_dir, table_name, num_records)
+}
+
+/// State left by an interrupted run, if any
+pub fn read_state(path: &str) -> Option<ResumeState> {
+    let file = fs::File::open(path).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+/// Records the settings of a starting run. Returns whether the run resumes an interrupted run with the
+/// same settings, in which case completed splits may be kept.
+pub fn start(path: &str, state: &ResumeState) -> DataGenResult<bool> {
+    let resume = match read_state(path) {
+        Some(previous) if previous == *state => true,
+        Some(_) => {
+            println!("Settings changed since the interrupted run, regenerating every split of {}", path);
+            false
+        }
+        None => false,
+    };
+    let json = serde_json::to_string(state).map_err(|e| WeirdCase {
+        message: format!("Failed to serialize the resume state {}: {}", path, e)
+    })?;
+    write_atomically(path, json.as_bytes())?;
+    Ok(resume)
+}
+
+/// Marks the run as complete, so the next run starts from scratch
+pub fn finish(path: &str) -> DataGenResult<()> {
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Path a file is written to before being renamed into place, so its final path only ever holds complete files
+pub fn temp_path(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+
+fn write_atomically(path: &str, bytes: &[u8]) -> DataGenResult<()> {
+    let temp = temp_path(path);
+    fs::write(&temp, bytes)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_only_with_the_same_settings() {
+        let output_dir = "./output_data/resume_state_test";
+        fs::create_dir_all(output_dir).unwrap();
+        let path = state_path(output_dir, "books", 10);
+        let state = ResumeState {
+            schema_sha256: "0f1e".to_string(),
+            seed: 42,
+            file_splits: 4,
+            zip_pack_batch_size: 2,
+            delimiter: ",".to_string(),
+            csv_options: CsvOptions::default(),
+            compression: "zip level 9".to_string(),
+        };
+
+        assert!(!start(&path, &state).unwrap());
+        assert_eq!(read_state(&path), Some(state.clone()));
+        assert!(start(&path, &state).unwrap());
+        assert!(!start(&path, &ResumeState { seed: 43, ..state.clone() }).unwrap());
+        assert!(!start(&path, &ResumeState { schema_sha256: "0f1f".to_string(), ..state.clone() }).unwrap());
+        assert!(!start(&path, &ResumeState { compression: "zip-stored".to_string(), ..state.clone() }).unwrap());
+        assert!(!start(&path, &ResumeState { csv_options: CsvOptions { header: true, ..CsvOptions::default() }, ..state.clone() }).unwrap());
+        finish(&path).unwrap();
+        assert!(!start(&path, &state).unwrap());
+
+        // Clean up test files
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir(output_dir);
+    }
+}
@@ -1,47 +1,867 @@
-use std::fs::File;
-use serde_yaml::Error;
-use crate::DType;
+use std::fs;
+use crate::{fakegen, DType, DValue};
+use crate::errors::{DataGenError, DataGenResult};
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Schema {
     pub name: String,
+    /// Seed of every generated value, so the same seed always yields the same dataset
+    pub seed: Option<u64>,
     pub dataset: DataSet,
 }
 
+/// A schema file declaring several datasets, generated together in foreign-key dependency order
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MultiSchema {
+    pub name: String,
+    /// Seed shared by all datasets and relationships, each deriving its own seed from its name
+    pub seed: Option<u64>,
+    pub datasets: Vec<DataSet>,
+    pub relationships: Option<Vec<Relationship>>,
+}
+
+/// A many-to-many relationship. Each row of the `owner` dataset is linked to a `cardinality` sized
+/// set of `target` keys, drawn from a generator seeded per owner row. Bitmap columns naming the
+/// relationship and its join table are both derived from these draws, so they hold the same associations.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Relationship {
+    pub name: String,
+    /// Serial column of the dataset whose rows own the associations, `<dataset>.<column>`
+    pub owner: String,
+    /// Serial column the associations point to, `<dataset>.<column>`
+    pub target: String,
+    pub cardinality: Cardinality,
+    /// Seed of the association draws, derived from the schema seed and the relationship name by default
+    pub seed: Option<u64>,
+    pub join_table: Option<JoinTable>,
+    /// Line of the relationship's name in the schema file, filled in when the file is parsed
+    #[serde(skip)]
+    pub line: Option<usize>,
+}
+
+/// Classic join table of a relationship with one `(owner, target)` row per association
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JoinTable {
+    pub name: String,
+    pub owner_column: String,
+    pub target_column: String,
+    pub file_splits: Option<usize>,
+    pub delimiter: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct DataSet {
     pub name: String,
+    /// Number of rows the dataset holds; required when another schema `references` it
+    pub num_records: Option<i64>,
+    /// Number of CSV files the dataset is split into by the `generate` command (1 by default)
+    pub file_splits: Option<usize>,
+    /// CSV delimiter used by the `generate` command ("," by default)
+    pub delimiter: Option<String>,
     pub columns: Vec<Column>,
+    /// Line of the dataset's name in the schema file, filled in when the file is parsed
+    #[serde(skip)]
+    pub line: Option<usize>,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Column {
     pub name: String,
     pub not_null: Option<bool>,
+    /// Share of the rows holding NULL, between 0 and 1; no NULL by default
+    pub null_ratio: Option<f64>,
+    /// Value held by a `default_ratio` share of the rows, e.g. a Rails migration default, written as in the schema
+    /// file and parsed for the dtype; bitmaps take an array such as `{1,2,3}`
+    pub default: Option<String>,
+    /// Share of the rows holding `default`, between 0 and 1; none by default
+    pub default_ratio: Option<f64>,
     pub dtype: DType,
     pub one_of: Option<Vec<String>>,
     pub min: Option<String>,
     pub max: Option<String>,
     pub mean: Option<f64>,
     pub std: Option<f64>,
-    pub format: Option<String>
+    pub format: Option<String>,
+    /// Foreign key to a parent serial column, written as `<dataset>.<column>`
+    pub references: Option<String>,
+    /// Number of ids per row of a bitmap column
+    pub cardinality: Option<Cardinality>,
+    /// Output encoding of a bitmap column, `array` by default
+    pub encoding: Option<BitmapEncoding>,
+    /// Name of the relationship a bitmap column is derived from
+    pub relationship: Option<String>,
+    /// Part of the table's primary key in the generated DDL
+    pub primary_key: Option<bool>,
+    /// Gets its own index in the generated DDL
+    pub index: Option<bool>,
+    /// Parent key space, filled in by `Schema::resolve_references`
+    #[serde(skip)]
+    pub key_space: Option<KeySpace>,
+    /// Seed of the per-row draws of a relationship column, filled in by `Schema::resolve_relationships`
+    #[serde(skip)]
+    pub seed: Option<u64>,
+    /// Line of the column's name in the schema file, filled in when the file is parsed
+    #[serde(skip)]
+    pub line: Option<usize>,
+}
 
+/// How many ids a bitmap column holds per row: a fixed count (`cardinality: 3`), a uniform
+/// inclusive range (`cardinality: {min: 1, max: 10}`) or a normal distribution (`cardinality: {mean: 4.2, std: 1.5}`)
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Cardinality {
+    Fixed(usize),
+    Range { min: usize, max: usize },
+    Normal { mean: f64, std: f64 },
+}
+
+/// `array` writes a bitmap as a `{1,2,3}` literal; `binary` writes the portable Roaring
+/// serialization (the 64-bit treemap format for `roaringbitmap64`) as a hex `bytea` literal
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum BitmapEncoding {
+    Array,
+    Binary,
+}
+
+/// The ids a serial column holds: `start`, `start + 1`, ..., `start + count - 1`
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub struct KeySpace {
+    pub start: i64,
+    pub count: i64,
 }
 
 impl Schema {
-    pub fn from(s: &str) -> Result<Schema, Error> {
-        serde_yaml::from_str(s)
+    /// Parses a schema, rejecting unknown keys and dtypes. See `validate` for the checks of the values.
+    pub fn from(s: &str) -> DataGenResult<Schema> {
+        let mut schema: Schema = serde_yaml::from_str(s).map_err(|e| DataGenError::WeirdCase { message: e.to_string() })?;
+        locate_lines(s, std::iter::once(&mut schema.dataset), &mut []);
+        Ok(schema)
+    }
+
+    pub fn from_path(path: String) -> DataGenResult<Schema> {
+        Schema::from(&read_schema_file(&path)?).map_err(|e| DataGenError::WeirdCase {
+            message: format!("Invalid schema file {}: {}", path, e)
+        })
+    }
+
+    /// Key space of one of this dataset's serial columns, derived from its `min` and the dataset's `num_records`
+    pub fn key_space(&self, column_name: &str) -> DataGenResult<KeySpace> {
+        let dataset = &self.dataset;
+        let column = dataset.columns.iter().find(|col| col.name == column_name)
+            .ok_or_else(|| DataGenError::WeirdCase {
+                message: format!("Dataset {} has no column {}", dataset.name, column_name)
+            })?;
+        if column.dtype != DType::Serial {
+            return Err(DataGenError::WeirdCase {
+                message: format!("Column {}.{} is referenced as a key but is not a serial column", dataset.name, column_name)
+            });
+        }
+        let count = dataset.num_records.ok_or_else(|| DataGenError::WeirdCase {
+            message: format!("Dataset {} is referenced by a foreign key but does not declare num_records", dataset.name)
+        })?;
+        let start = match &column.min {
+            Some(min) => min.parse::<i64>().map_err(|e| DataGenError::WeirdCase {
+                message: format!("Invalid min {} for serial column {}.{}: {}", min, dataset.name, column_name, e)
+            })?,
+            None => 1,
+        };
+        Ok(KeySpace { start, count })
+    }
+
+    /// Checks every option of the dataset and its columns, so that a schema fails before anything is
+    /// generated, listing all of its problems, rather than with a panic in the middle of a run
+    pub fn validate(&self) -> DataGenResult<()> {
+        check(&self.name, self.problems())
+    }
+
+    /// Problems of the dataset and its columns, each naming them and the line of the schema file
+    pub fn problems(&self) -> Vec<String> {
+        dataset_problems(&self.dataset)
+    }
+
+    /// Resolves every `references` column against the key spaces of the given parent schemas
+    pub fn resolve_references(&mut self, parents: &[Schema]) -> DataGenResult<()> {
+        let dataset_name = self.dataset.name.clone();
+        for column in self.dataset.columns.iter_mut() {
+            let reference = match &column.references {
+                Some(reference) => reference,
+                None => continue,
+            };
+            let (parent_name, parent_column) = parse_reference(reference).ok_or_else(|| DataGenError::WeirdCase {
+                message: format!("Column {}.{} has an invalid reference {}, expected <dataset>.<column>", dataset_name, column.name, reference)
+            })?;
+            let parent = parents.iter().find(|parent| parent.dataset.name == parent_name)
+                .ok_or_else(|| DataGenError::WeirdCase {
+                    message: format!("Column {}.{} references unknown dataset {}", dataset_name, column.name, parent_name)
+                })?;
+            column.key_space = Some(parent.key_space(parent_column)?);
+        }
+        Ok(())
+    }
+
+    /// Resolves every bitmap column derived from a relationship: its ids come from the relationship's
+    /// target key space, cardinality and seed, keyed by this dataset's row index as the owner row
+    pub fn resolve_relationships(&mut self, relationships: &[Relationship], schemas: &[Schema]) -> DataGenResult<()> {
+        let dataset_name = self.dataset.name.clone();
+        let num_records = self.dataset.num_records;
+        for column in self.dataset.columns.iter_mut() {
+            let name = match &column.relationship {
+                Some(name) => name,
+                None => continue,
+            };
+            let relationship = relationships.iter().find(|relationship| &relationship.name == name)
+                .ok_or_else(|| DataGenError::WeirdCase {
+                    message: format!("Column {}.{} uses unknown relationship {}", dataset_name, column.name, name)
+                })?;
+            if !column.dtype.is_bitmap() {
+                return Err(DataGenError::WeirdCase {
+                    message: format!("Column {}.{} uses relationship {} but is not a bitmap column", dataset_name, column.name, name)
+                });
+            }
+            if column.references.is_some() || column.cardinality.is_some() {
+                return Err(DataGenError::WeirdCase {
+                    message: format!("Column {}.{} takes its ids and cardinality from relationship {}, remove references and cardinality", dataset_name, column.name, name)
+                });
+            }
+            let (owner, target) = relationship.key_spaces(schemas)?;
+            if num_records != Some(owner.count) {
+                return Err(DataGenError::WeirdCase {
+                    message: format!("Column {}.{} uses relationship {} but dataset {} does not have the owner's {} rows", dataset_name, column.name, name, dataset_name, owner.count)
+                });
+            }
+            column.key_space = Some(target);
+            column.cardinality = Some(relationship.cardinality.clone());
+            column.seed = Some(relationship.seed(self.seed.unwrap_or_default()));
+        }
+        Ok(())
+    }
+
+    /// Sets the schema seed and derives from it the seed of every column, by dataset and column
+    /// name. Relationship columns keep the relationship seed.
+    pub fn resolve_seeds(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        let dataset_seed = fakegen::derive_seed(seed, &self.dataset.name);
+        for column in self.dataset.columns.iter_mut() {
+            if column.seed.is_none() {
+                column.seed = Some(fakegen::derive_seed(dataset_seed, &column.name));
+            }
+        }
+    }
+}
+
+impl Column {
+    /// `default` parsed into a value of the column's dtype, or why it does not parse
+    pub fn default_value(&self) -> Result<Option<DValue>, String> {
+        self.default.as_deref().map(|text| self.parse_value(text)).transpose()
+    }
+
+    /// Value of the column's dtype written as `text` in the schema file, e.g. a `default` or a `one_of` value
+    pub fn parse_value(&self, text: &str) -> Result<DValue, String> {
+        let format = self.format.as_deref().unwrap_or_default();
+        use DType::*;
+        let value = match self.dtype {
+            Boolean => DValue::Boolean(text.parse().map_err(|e| format!("{}", e))?),
+            Int | Age => DValue::Int(text.parse().map_err(|e| format!("{}", e))?),
+            Long | Serial => DValue::Long(text.parse().map_err(|e| format!("{}", e))?),
+            Float => DValue::Float(text.parse().map_err(|e| format!("{}", e))?),
+            Double => DValue::Double(text.parse().map_err(|e| format!("{}", e))?),
+            String | Name | City | Phone | Latitude | Longitude => DValue::Str(text.to_string()),
+            Date => {
+                fakegen::parse_date(self, text).ok_or_else(|| format!("expected a date of format {:?}", format))?;
+                DValue::Date(text.to_string())
+            }
+            DateTime => {
+                fakegen::parse_datetime(self, text).ok_or_else(|| format!("expected a datetime of format {:?}", format))?;
+                DValue::DateTime(text.to_string())
+            }
+            RoaringBitmap | RoaringBitmap64 => {
+                let mut ids = text.trim().trim_start_matches('{').trim_end_matches('}')
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(|id| id.parse::<i64>().ok().filter(|id| *id >= 0).ok_or_else(|| format!("{} is not a bitmap id", id)))
+                    .collect::<Result<Vec<i64>, std::string::String>>()?;
+                ids.sort_unstable();
+                ids.dedup();
+                match self.encoding {
+                    Some(BitmapEncoding::Binary) => DValue::Bytes(fakegen::serialize_bitmap(&self.dtype, &ids)),
+                    _ => DValue::IdSet(ids),
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    /// Problems of the column's options, each phrased to follow the column's name
+    fn problems(&self) -> Vec<String> {
+        use DType::*;
+        let mut problems = Vec::new();
+        let dtype = dtype_name(&self.dtype);
+        let numeric = matches!(self.dtype, Int | Long | Float | Double);
+        let temporal = matches!(self.dtype, Date | DateTime);
+        let keyed = self.references.is_some() || self.relationship.is_some();
+
+        for &(option, ratio) in &[("null_ratio", self.null_ratio), ("default_ratio", self.default_ratio)] {
+            match ratio {
+                Some(ratio) if !(0.0..=1.0).contains(&ratio) => {
+                    problems.push(format!("has {} {}, expected a value between 0 and 1", option, ratio));
+                }
+                // Primary keys can neither be NULL nor repeat a default, and the join table of a
+                // relationship would hold associations the bitmap column lacks
+                Some(ratio) if ratio > 0.0 => {
+                    let conflict = if option == "null_ratio" && self.not_null == Some(true) {
+                        Some("not_null: true")
+                    } else if self.primary_key == Some(true) {
+                        Some("primary_key: true")
+                    } else if option == "default_ratio" && self.dtype == Serial {
+                        Some("is a serial column")
+                    } else if self.relationship.is_some() {
+                        Some("uses a relationship")
+                    } else {
+                        None
+                    };
+                    if let Some(conflict) = conflict {
+                        problems.push(format!("has {} {} but {}", option, ratio, conflict));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Err(reason) = self.default_value() {
+            problems.push(format!("has an invalid default {:?}: {}", self.default.as_deref().unwrap_or_default(), reason));
+        }
+        let (null_ratio, default_ratio) = (self.null_ratio.unwrap_or_default(), self.default_ratio.unwrap_or_default());
+        if self.default.is_none() && default_ratio > 0.0 {
+            problems.push(format!("has default_ratio {} but no default", default_ratio));
+        }
+        // Out of range ratios are reported above
+        if null_ratio <= 1.0 && default_ratio <= 1.0 && null_ratio + default_ratio > 1.0 {
+            problems.push(format!("has null_ratio {} and default_ratio {} adding up to more than 1", null_ratio, default_ratio));
+        }
+
+        if temporal && self.format.is_none() {
+            problems.push(format!("is a {} column without a format", dtype));
+        }
+        if let Some(reference) = &self.references {
+            if parse_reference(reference).is_none() {
+                problems.push(format!("has an invalid reference {}, expected <dataset>.<column>", reference));
+            }
+            if !matches!(self.dtype, Int | Long) && !self.dtype.is_bitmap() {
+                problems.push(format!("has references, which {} columns do not take, expected int or long", dtype));
+            }
+        }
+        // Foreign keys are drawn from the referenced key space, which the resolved key space tells is empty
+        if self.references.is_some() && !self.dtype.is_bitmap() && self.key_space.is_some_and(|key_space| key_space.count == 0) {
+            problems.push("references a dataset without rows, so there is no key to draw".to_string());
+        }
+        // Keys beyond 32 bits would wrap around in an int column
+        if let Some(key_space) = self.key_space.filter(|key_space| key_space.count > 0 && self.dtype == Int) {
+            let last = key_space.start.saturating_add(key_space.count - 1);
+            if key_space.start < i64::from(i32::MIN) || last > i64::from(i32::MAX) {
+                problems.push(format!("references keys {} to {}, beyond the 32-bit values of an int column, use long", key_space.start, last));
+            }
+        }
+
+        // `min` and `max` bound the values, the first id of a serial column or the ids of a bitmap column
+        let bounds = (self.min.as_deref(), self.max.as_deref());
+        if self.dtype == Serial {
+            if let Some(min) = bounds.0.filter(|min| min.parse::<i64>().is_err()) {
+                problems.push(format!("has min {:?}, expected the integer first id", min));
+            }
+            if bounds.1.is_some() {
+                problems.push("has a max, which serial columns do not take".to_string());
+            }
+        } else if self.dtype.is_bitmap() {
+            // Ids drawn from a referenced key space must fit the bitmap as much as explicit bounds do
+            if let Some(key_space) = self.key_space.filter(|key_space| key_space.count > 0) {
+                let last = key_space.start.saturating_add(key_space.count - 1);
+                if key_space.start < 0 {
+                    problems.push(format!("draws ids from {} on, but bitmap ids cannot be negative", key_space.start));
+                } else if self.dtype == RoaringBitmap && last > i64::from(i32::MAX) {
+                    problems.push(format!("draws ids up to {}, beyond the 32-bit ids of a roaringbitmap, use roaringbitmap64", last));
+                }
+            }
+            if !keyed {
+                match bounds {
+                    (Some(min), Some(max)) => match (min.parse::<i64>(), max.parse::<i64>()) {
+                        (Ok(min), Ok(_)) if min < 0 => problems.push(format!("has min {}, but bitmap ids cannot be negative", min)),
+                        (Ok(min), Ok(max)) if min > max => problems.push(format!("has min {} above max {}", min, max)),
+                        (Ok(_), Ok(max)) if self.dtype == RoaringBitmap && max > i64::from(i32::MAX) => {
+                            problems.push(format!("has max {} beyond the 32-bit ids of a roaringbitmap, use roaringbitmap64", max));
+                        }
+                        (Ok(_), Ok(_)) => {}
+                        _ => problems.push(format!("has min {:?} and max {:?}, expected integer ids", min, max)),
+                    },
+                    _ => problems.push("is a bitmap column without references or min and max to draw its ids from".to_string()),
+                }
+            }
+            if self.relationship.is_none() && self.cardinality.is_none() {
+                problems.push("is a bitmap column without a cardinality".to_string());
+            }
+        } else if keyed {
+            // Foreign keys are drawn from the referenced key space
+        } else if numeric || temporal {
+            match bounds {
+                (Some(min), Some(max)) => {
+                    let parse = |text: &str| match self.dtype {
+                        Date => fakegen::date_to_days(self, text).map(f64::from),
+                        DateTime => fakegen::datetime_to_millis(self, text).map(|millis| millis as f64),
+                        _ => text.parse::<f64>().ok(),
+                    };
+                    match (parse(min), parse(max)) {
+                        (Some(from), Some(to)) if from >= to => problems.push(format!("has min {} not below max {}", min, max)),
+                        (Some(_), Some(_)) => {}
+                        // A missing format is reported above
+                        _ if temporal && self.format.is_none() => {}
+                        _ if temporal => problems.push(format!("has min {:?} and max {:?} not matching its format {:?}",
+                                                                min, max, self.format.as_deref().unwrap_or_default())),
+                        _ => problems.push(format!("has min {:?} and max {:?}, expected numbers", min, max)),
+                    }
+                }
+                (None, None) => {}
+                _ => problems.push("sets only one of min and max".to_string()),
+            }
+        } else if bounds != (None, None) {
+            problems.push(format!("has min or max, which {} columns do not take", dtype));
+        }
+
+        match (self.mean, self.std) {
+            (None, None) => {}
+            (Some(mean), Some(std)) if numeric => {
+                if !mean.is_finite() || !std.is_finite() || std < 0.0 {
+                    problems.push(format!("has mean {} and std {}, expected a finite mean and a non-negative std", mean, std));
+                }
+            }
+            _ if !numeric => problems.push(format!("has mean or std, which {} columns do not take", dtype)),
+            _ => problems.push("sets only one of mean and std".to_string()),
+        }
+
+        if let Some(one_of) = &self.one_of {
+            if keyed || !matches!(self.dtype, Boolean | Int | Long | Float | Double | String | Date | DateTime) {
+                let taker = if keyed { "foreign key and relationship".to_string() } else { dtype.clone() };
+                problems.push(format!("has one_of, which {} columns do not take", taker));
+            } else if one_of.is_empty() {
+                problems.push("has an empty one_of".to_string());
+            } else {
+                for value in one_of {
+                    if let Err(reason) = self.parse_value(value) {
+                        problems.push(format!("has an invalid one_of value {:?}: {}", value, reason));
+                    }
+                }
+            }
+        }
+
+        if !self.dtype.is_bitmap() {
+            for (option, set) in &[("cardinality", self.cardinality.is_some()), ("encoding", self.encoding.is_some()), ("relationship", self.relationship.is_some())] {
+                if *set {
+                    problems.push(format!("has a {}, which only bitmap columns take", option));
+                }
+            }
+        }
+        if let Some(problem) = self.cardinality.as_ref().and_then(cardinality_problem) {
+            problems.push(problem);
+        }
+        problems
+    }
+}
+
+impl Relationship {
+    /// Key spaces of the owner and target serial columns
+    pub fn key_spaces(&self, schemas: &[Schema]) -> DataGenResult<(KeySpace, KeySpace)> {
+        Ok((self.key_space(&self.owner, schemas)?, self.key_space(&self.target, schemas)?))
+    }
+
+    fn key_space(&self, reference: &str, schemas: &[Schema]) -> DataGenResult<KeySpace> {
+        let (dataset_name, column_name) = parse_reference(reference).ok_or_else(|| DataGenError::WeirdCase {
+            message: format!("Relationship {} has an invalid key {}, expected <dataset>.<column>", self.name, reference)
+        })?;
+        let schema = schemas.iter().find(|schema| schema.dataset.name == dataset_name)
+            .ok_or_else(|| DataGenError::WeirdCase {
+                message: format!("Relationship {} references unknown dataset {}", self.name, dataset_name)
+            })?;
+        schema.key_space(column_name)
+    }
+
+    pub fn seed(&self, schema_seed: u64) -> u64 {
+        self.seed.unwrap_or_else(|| fakegen::derive_seed(schema_seed, &self.name))
+    }
+
+    /// Datasets the relationship reads key spaces from
+    fn datasets(&self) -> Vec<&str> {
+        [&self.owner, &self.target].iter()
+            .filter_map(|reference| parse_reference(reference).map(|(dataset, _)| dataset))
+            .collect()
+    }
+}
+
+impl JoinTable {
+    /// Single-dataset schema of the join table's two bigint columns
+    pub fn schema(&self) -> Schema {
+        let key_column = |name: &str| Column {
+            name: name.to_string(),
+            not_null: Some(true),
+            null_ratio: None,
+            default: None,
+            default_ratio: None,
+            dtype: DType::Long,
+            one_of: None,
+            min: None,
+            max: None,
+            mean: None,
+            std: None,
+            format: None,
+            references: None,
+            cardinality: None,
+            encoding: None,
+            relationship: None,
+            // The owner and target pair of an association is unique
+            primary_key: Some(true),
+            index: None,
+            key_space: None,
+            seed: None,
+            line: None,
+        };
+        Schema {
+            name: self.name.clone(),
+            seed: None,
+            dataset: DataSet {
+                name: self.name.clone(),
+                num_records: None,
+                file_splits: self.file_splits,
+                delimiter: self.delimiter.clone(),
+                columns: vec![key_column(&self.owner_column), key_column(&self.target_column)],
+                line: None,
+            },
+        }
+    }
+}
+
+impl MultiSchema {
+    /// Parses a multi-dataset schema, rejecting unknown keys and dtypes. See `validate` for the checks of the values.
+    pub fn from(s: &str) -> DataGenResult<MultiSchema> {
+        let mut multi_schema: MultiSchema = serde_yaml::from_str(s).map_err(|e| DataGenError::WeirdCase { message: e.to_string() })?;
+        locate_lines(s, multi_schema.datasets.iter_mut(), multi_schema.relationships.as_deref_mut().unwrap_or_default());
+        Ok(multi_schema)
+    }
+
+    pub fn from_path(path: String) -> DataGenResult<MultiSchema> {
+        MultiSchema::from(&read_schema_file(&path)?).map_err(|e| DataGenError::WeirdCase {
+            message: format!("Invalid schema file {}: {}", path, e)
+        })
+    }
+
+    /// Checks every dataset as `Schema::validate` does, along with the relationships
+    pub fn validate(&self) -> DataGenResult<()> {
+        check(&self.name, self.problems())
+    }
+
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (i, dataset) in self.datasets.iter().enumerate() {
+            if self.datasets[..i].iter().any(|other| other.name == dataset.name) {
+                problems.push(located(format!("Dataset {} is declared more than once", dataset.name), dataset.line));
+            }
+            problems.extend(dataset_problems(dataset));
+            for column in dataset.columns.iter().filter(|column| !column.dtype.is_bitmap()) {
+                let parent = column.references.as_deref().and_then(parse_reference)
+                    .and_then(|(parent_name, _)| self.datasets.iter().find(|other| other.name == parent_name));
+                if let Some(parent) = parent.filter(|parent| parent.num_records == Some(0)) {
+                    let problem = format!("Column {}.{} references dataset {} without rows, so there is no key to draw", dataset.name, column.name, parent.name);
+                    problems.push(located(problem, column.line));
+                }
+            }
+        }
+        for relationship in self.relationships.iter().flatten() {
+            let mut relationship_problems = Vec::new();
+            for key in &[&relationship.owner, &relationship.target] {
+                match parse_reference(key) {
+                    None => relationship_problems.push(format!("has an invalid key {}, expected <dataset>.<column>", key)),
+                    Some((dataset, _)) if !self.datasets.iter().any(|other| other.name == dataset) => {
+                        relationship_problems.push(format!("references unknown dataset {}", dataset));
+                    }
+                    Some(_) => {}
+                }
+            }
+            relationship_problems.extend(cardinality_problem(&relationship.cardinality));
+            let delimiter = relationship.join_table.as_ref().and_then(|join_table| join_table.delimiter.as_ref());
+            if let Some(delimiter) = delimiter.filter(|delimiter| delimiter.len() != 1) {
+                relationship_problems.push(format!("has join table delimiter {:?}, expected a single character", delimiter));
+            }
+            problems.extend(relationship_problems.into_iter()
+                .map(|problem| located(format!("Relationship {} {}", relationship.name, problem), relationship.line)));
+        }
+        problems
+    }
+
+    /// Returns one single-dataset `Schema` per dataset, with references resolved, ordered so that
+    /// every dataset comes after the datasets it references. Fails on cycles and unknown datasets.
+    pub fn ordered_schemas(&self) -> DataGenResult<Vec<Schema>> {
+        let schemas: Vec<Schema> = self.datasets.iter()
+            .map(|dataset| Schema { name: self.name.clone(), seed: self.seed, dataset: dataset.clone() })
+            .collect();
+        self.validate()?;
+        let relationships: &[Relationship] = self.relationships.as_deref().unwrap_or_default();
+
+        // Kahn's algorithm over the reference graph, keeping declaration order among ready datasets
+        let mut parents: Vec<Vec<usize>> = Vec::with_capacity(schemas.len());
+        for schema in &schemas {
+            let mut deps = Vec::new();
+            for column in &schema.dataset.columns {
+                let reference = match &column.references {
+                    Some(reference) => reference,
+                    None => continue,
+                };
+                let parent_name = parse_reference(reference).map(|(dataset, _)| dataset).ok_or_else(|| DataGenError::WeirdCase {
+                    message: format!("Column {}.{} has an invalid reference {}, expected <dataset>.<column>", schema.dataset.name, column.name, reference)
+                })?;
+                let parent = schemas.iter().position(|parent| parent.dataset.name == parent_name)
+                    .ok_or_else(|| DataGenError::WeirdCase {
+                        message: format!("Column {}.{} references unknown dataset {}", schema.dataset.name, column.name, parent_name)
+                    })?;
+                // A dataset referencing its own key (e.g. a parent_id) does not constrain the order
+                if parent_name != schema.dataset.name && !deps.contains(&parent) {
+                    deps.push(parent);
+                }
+            }
+            for column in &schema.dataset.columns {
+                let relationship = match &column.relationship {
+                    Some(name) => relationships.iter().find(|relationship| &relationship.name == name),
+                    None => continue,
+                };
+                for parent_name in relationship.map(|relationship| relationship.datasets()).unwrap_or_default() {
+                    let parent = schemas.iter().position(|parent| parent.dataset.name == parent_name)
+                        .ok_or_else(|| DataGenError::WeirdCase {
+                            message: format!("Column {}.{} uses a relationship on unknown dataset {}", schema.dataset.name, column.name, parent_name)
+                        })?;
+                    if parent_name != schema.dataset.name && !deps.contains(&parent) {
+                        deps.push(parent);
+                    }
+                }
+            }
+            parents.push(deps);
+        }
+
+        let mut order: Vec<usize> = Vec::with_capacity(schemas.len());
+        while order.len() < schemas.len() {
+            let next = (0..schemas.len()).find(|i| {
+                !order.contains(i) && parents[*i].iter().all(|parent| order.contains(parent))
+            });
+            match next {
+                Some(i) => order.push(i),
+                None => {
+                    let cycle: Vec<&str> = (0..schemas.len())
+                        .filter(|i| !order.contains(i))
+                        .map(|i| schemas[i].dataset.name.as_str())
+                        .collect();
+                    return Err(DataGenError::WeirdCase {
+                        message: format!("Foreign key cycle between datasets {}", cycle.join(", "))
+                    });
+                }
+            }
+        }
+
+        let ordered = order.into_iter()
+            .map(|i| {
+                let mut schema = schemas[i].clone();
+                schema.resolve_references(&schemas)?;
+                schema.resolve_relationships(relationships, &schemas)?;
+                Ok(schema)
+            })
+            .collect::<DataGenResult<Vec<Schema>>>()?;
+        // Validated again once resolved, which tells whether the referenced key spaces fit their columns
+        check(&self.name, ordered.iter().flat_map(Schema::problems).collect())?;
+        Ok(ordered)
+    }
+}
+
+fn read_schema_file(path: &str) -> DataGenResult<String> {
+    fs::read_to_string(path).map_err(|e| DataGenError::WeirdCase { message: format!("Unable to read the schema file {}: {}", path, e) })
+}
+
+/// Fails with every problem found, one per line
+fn check(schema_name: &str, problems: Vec<String>) -> DataGenResult<()> {
+    match problems.len() {
+        0 => Ok(()),
+        1 => Err(DataGenError::WeirdCase { message: problems[0].clone() }),
+        count => Err(DataGenError::WeirdCase {
+            message: format!("Schema {} has {} problems:\n{}", schema_name, count, problems.join("\n"))
+        }),
+    }
+}
+
+/// Adds the line of the schema file a problem comes from, when known
+fn located(problem: String, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{} (line {})", problem, line),
+        None => problem,
+    }
+}
+
+fn dataset_problems(dataset: &DataSet) -> Vec<String> {
+    let mut problems = Vec::new();
+    if dataset.columns.is_empty() {
+        problems.push("has no columns".to_string());
+    }
+    if let Some(num_records) = dataset.num_records.filter(|num_records| *num_records < 0) {
+        problems.push(format!("has num_records {}, expected a positive count", num_records));
+    }
+    if dataset.file_splits == Some(0) {
+        problems.push("has file_splits 0, expected at least 1".to_string());
+    }
+    if let Some(delimiter) = dataset.delimiter.as_ref().filter(|delimiter| delimiter.len() != 1) {
+        problems.push(format!("has delimiter {:?}, expected a single character", delimiter));
+    }
+    for (i, column) in dataset.columns.iter().enumerate() {
+        if dataset.columns[..i].iter().any(|other| other.name == column.name) {
+            problems.push(format!("has more than one column named {}", column.name));
+        }
+    }
+    let mut problems: Vec<String> = problems.into_iter()
+        .map(|problem| located(format!("Dataset {} {}", dataset.name, problem), dataset.line))
+        .collect();
+    for column in &dataset.columns {
+        problems.extend(column.problems().into_iter()
+            .map(|problem| located(format!("Column {}.{} {}", dataset.name, column.name, problem), column.line)));
+    }
+    problems
+}
+
+fn cardinality_problem(cardinality: &Cardinality) -> Option<String> {
+    match *cardinality {
+        Cardinality::Range { min, max } if min > max => Some(format!("has a cardinality from {} to {}, expected min up to max", min, max)),
+        Cardinality::Normal { mean, std } if !mean.is_finite() || !std.is_finite() || std < 0.0 => {
+            Some(format!("has a cardinality of mean {} and std {}, expected a finite mean and a non-negative std", mean, std))
+        }
+        _ => None,
+    }
+}
+
+/// Name of the dtype in the schema file
+fn dtype_name(dtype: &DType) -> String {
+    format!("{:?}", dtype).to_lowercase()
+}
+
+/// Fills in the schema file lines of the datasets and their columns, and of the relationships: the lines
+/// of their `name` values, as the YAML parser marks them
+fn locate_lines<'a>(yaml: &str, datasets: impl Iterator<Item = &'a mut DataSet>, relationships: &mut [Relationship]) {
+    let mut tree = YamlTree::default();
+    if Parser::new(yaml.chars()).load(&mut tree, false).is_err() {
+        return;
+    }
+    let root = match &tree.root {
+        Some(root) => root,
+        None => return,
+    };
+    let name_line = |node: &YamlNode| node.get("name").map(|name| name.line);
+    // A single-dataset schema has one `dataset`, a multi-dataset schema a `datasets` list
+    let dataset_nodes: Vec<&YamlNode> = match root.get("dataset") {
+        Some(dataset) => vec![dataset],
+        None => root.get("datasets").map(YamlNode::items).unwrap_or_default().iter().collect(),
+    };
+    for (dataset, node) in datasets.zip(dataset_nodes) {
+        dataset.line = name_line(node);
+        let column_nodes = node.get("columns").map(YamlNode::items).unwrap_or_default();
+        for (column, node) in dataset.columns.iter_mut().zip(column_nodes) {
+            column.line = name_line(node);
+        }
+    }
+    let relationship_nodes = root.get("relationships").map(YamlNode::items).unwrap_or_default();
+    for (relationship, node) in relationships.iter_mut().zip(relationship_nodes) {
+        relationship.line = name_line(node);
+    }
+}
+
+/// Node of a schema file and the line it starts on
+struct YamlNode {
+    line: usize,
+    value: YamlValue,
+}
+
+enum YamlValue {
+    Scalar(String),
+    Sequence(Vec<YamlNode>),
+    /// Keys and values, alternating
+    Mapping(Vec<YamlNode>),
+    Alias,
+}
+
+impl YamlNode {
+    /// Value of a mapping's key
+    fn get(&self, key: &str) -> Option<&YamlNode> {
+        match &self.value {
+            YamlValue::Mapping(entries) => entries.chunks(2)
+                .find(|entry| matches!(&entry[0].value, YamlValue::Scalar(name) if name == key))
+                .and_then(|entry| entry.get(1)),
+            _ => None,
+        }
     }
 
-    pub fn from_path(path: String) -> Result<Schema, Error> {
-        let file = File::open(path).expect("Unable to open the file");
-        serde_yaml::from_reader(file)
+    /// Items of a sequence
+    fn items(&self) -> &[YamlNode] {
+        match &self.value {
+            YamlValue::Sequence(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Builds the node tree of a document from the parser's marked events
+#[derive(Default)]
+struct YamlTree {
+    /// Sequences and mappings started but not ended yet, innermost last
+    open: Vec<YamlNode>,
+    root: Option<YamlNode>,
+}
+
+impl MarkedEventReceiver for YamlTree {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let line = mark.line();
+        let node = match event {
+            Event::SequenceStart(_) => {
+                self.open.push(YamlNode { line, value: YamlValue::Sequence(Vec::new()) });
+                return;
+            }
+            Event::MappingStart(_) => {
+                self.open.push(YamlNode { line, value: YamlValue::Mapping(Vec::new()) });
+                return;
+            }
+            Event::SequenceEnd | Event::MappingEnd => match self.open.pop() {
+                Some(node) => node,
+                None => return,
+            },
+            Event::Scalar(value, ..) => YamlNode { line, value: YamlValue::Scalar(value) },
+            Event::Alias(_) => YamlNode { line, value: YamlValue::Alias },
+            _ => return,
+        };
+        match self.open.last_mut() {
+            Some(YamlNode { value: YamlValue::Sequence(items), .. }) | Some(YamlNode { value: YamlValue::Mapping(items), .. }) => items.push(node),
+            _ => self.root = Some(node),
+        }
+    }
+}
+
+/// Splits a `<dataset>.<column>` reference into its two parts
+pub fn parse_reference(reference: &str) -> Option<(&str, &str)> {
+    let mut parts = reference.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(dataset), Some(column)) if !dataset.is_empty() && !column.is_empty() => Some((dataset, column)),
+        _ => None,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::schema::Schema;
+    use crate::schema::{Cardinality, KeySpace, MultiSchema, Schema};
+    use crate::DValue;
 
     #[test]
     fn derive_struct_from_yaml() {
@@ -68,14 +888,377 @@ dataset:
             dtype: string
 "#;
 
-        let schema = Schema::from(&yaml);
-        pretty_assertions::assert_eq!(format ! ("{:?}", schema.unwrap()), r#"Schema { name: "person_schema", dataset: DataSet { name: "person_table", columns: [Column { name: "id", not_null: Some(false), dtype: Int, one_of: None, min: None, max: None, mean: None, std: None, format: None }, Column { name: "name", not_null: None, dtype: String, one_of: None, min: None, max: None, mean: None, std: None, format: None }, Column { name: "age", not_null: None, dtype: Int, one_of: None, min: None, max: None, mean: None, std: None, format: None }, Column { name: "adult", not_null: None, dtype: Boolean, one_of: None, min: None, max: None, mean: None, std: None, format: None }, Column { name: "gender", not_null: None, dtype: String, one_of: None, min: None, max: None, mean: None, std: None, format: None }] } }"#);
+        let schema = Schema::from(yaml);
+        pretty_assertions::assert_eq!(format ! ("{:?}", schema.unwrap()), r#"Schema { name: "person_schema", seed: None, dataset: DataSet { name: "person_table", num_records: None, file_splits: None, delimiter: None, columns: [Column { name: "id", not_null: Some(false), null_ratio: None, default: None, default_ratio: None, dtype: Int, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(6) }, Column { name: "name", not_null: None, null_ratio: None, default: None, default_ratio: None, dtype: String, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(10) }, Column { name: "age", not_null: None, null_ratio: None, default: None, default_ratio: None, dtype: Int, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(13) }, Column { name: "adult", not_null: None, null_ratio: None, default: Some("false"), default_ratio: None, dtype: Boolean, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(16) }, Column { name: "gender", not_null: None, null_ratio: None, default: None, default_ratio: None, dtype: String, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(20) }], line: Some(3) } }"#);
     }
 
     #[test]
     fn derive_struct_from_file() {
         let file_path = "./test_data/schema_simple.yaml".to_string();
         let schema = Schema::from_path(file_path);
-        pretty_assertions::assert_eq!(format!("{:?}", schema.unwrap()), r#"Schema { name: "person_schema", dataset: DataSet { name: "person_table", columns: [Column { name: "id", not_null: Some(false), dtype: Int, one_of: None, min: None, max: None, mean: None, std: None, format: None }, Column { name: "name", not_null: None, dtype: Name, one_of: None, min: None, max: None, mean: None, std: None, format: None }, Column { name: "age", not_null: None, dtype: Age, one_of: None, min: None, max: None, mean: None, std: None, format: None }, Column { name: "adult", not_null: None, dtype: Boolean, one_of: None, min: None, max: None, mean: None, std: None, format: None }, Column { name: "gender", not_null: None, dtype: String, one_of: Some(["M", "F"]), min: None, max: None, mean: None, std: None, format: None }, Column { name: "date", not_null: None, dtype: Date, one_of: None, min: Some("01/01/2014"), max: Some("03/01/2014"), mean: None, std: None, format: Some("%d/%m/%Y") }] } }"#);
+        pretty_assertions::assert_eq!(format!("{:?}", schema.unwrap()), r#"Schema { name: "person_schema", seed: None, dataset: DataSet { name: "person_table", num_records: None, file_splits: None, delimiter: None, columns: [Column { name: "id", not_null: Some(false), null_ratio: None, default: None, default_ratio: None, dtype: Int, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(6) }, Column { name: "name", not_null: None, null_ratio: None, default: None, default_ratio: None, dtype: Name, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(7) }, Column { name: "age", not_null: None, null_ratio: None, default: None, default_ratio: None, dtype: Age, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(8) }, Column { name: "adult", not_null: None, null_ratio: None, default: Some("false"), default_ratio: None, dtype: Boolean, one_of: None, min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(9) }, Column { name: "gender", not_null: None, null_ratio: None, default: None, default_ratio: None, dtype: String, one_of: Some(["M", "F"]), min: None, max: None, mean: None, std: None, format: None, references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(10) }, Column { name: "date", not_null: None, null_ratio: None, default: None, default_ratio: None, dtype: Date, one_of: None, min: Some("01/01/2014"), max: Some("03/01/2014"), mean: None, std: None, format: Some("%d/%m/%Y"), references: None, cardinality: None, encoding: None, relationship: None, primary_key: None, index: None, key_space: None, seed: None, line: Some(11) }], line: Some(4) } }"#);
+    }
+
+    #[test]
+    fn derive_bitmap_cardinality_from_yaml() {
+        let schema = Schema::from(r#"name: orders_schema
+dataset:
+    name: orders
+    columns:
+        - {name: fixed_ids, dtype: roaringbitmap, min: 1, max: 100, cardinality: 3}
+        - {name: range_ids, dtype: roaringbitmap64, min: 1, max: 100, cardinality: {min: 1, max: 10}}
+        - {name: normal_ids, dtype: roaringbitmap64, min: 1, max: 100, cardinality: {mean: 4.2, std: 1.5}}
+"#).unwrap();
+        let cardinalities: Vec<Option<Cardinality>> = schema.dataset.columns.into_iter().map(|col| col.cardinality).collect();
+        assert_eq!(cardinalities, vec![
+            Some(Cardinality::Fixed(3)),
+            Some(Cardinality::Range { min: 1, max: 10 }),
+            Some(Cardinality::Normal { mean: 4.2, std: 1.5 }),
+        ]);
+    }
+
+    #[test]
+    fn resolve_references_from_parent_key_space() {
+        let parent = Schema::from(r#"name: authors_schema
+dataset:
+    name: authors
+    num_records: 500
+    columns:
+        - {name: id, dtype: serial, min: 10}
+        - {name: name, dtype: name}
+"#).unwrap();
+        let mut child = Schema::from(r#"name: books_schema
+dataset:
+    name: books
+    columns:
+        - {name: id, dtype: serial}
+        - {name: author_id, dtype: long, references: authors.id}
+"#).unwrap();
+
+        child.resolve_references(std::slice::from_ref(&parent)).unwrap();
+        assert_eq!(child.dataset.columns[0].key_space, None);
+        assert_eq!(child.dataset.columns[1].key_space, Some(KeySpace { start: 10, count: 500 }));
+
+        let mut orphan = child.clone();
+        orphan.dataset.columns[1].references = Some("publishers.id".to_string());
+        assert!(orphan.resolve_references(std::slice::from_ref(&parent)).is_err());
+
+        let mut not_a_key = child.clone();
+        not_a_key.dataset.columns[1].references = Some("authors.name".to_string());
+        assert!(not_a_key.resolve_references(std::slice::from_ref(&parent)).is_err());
+
+        let mut unsized_parent = parent;
+        unsized_parent.dataset.num_records = None;
+        assert!(child.resolve_references(&[unsized_parent]).is_err());
+    }
+
+    #[test]
+    fn order_multi_schema_by_references() {
+        let multi = MultiSchema::from_path("./test_data/schema_multi.yaml".to_string()).unwrap();
+        let schemas = multi.ordered_schemas().unwrap();
+        let names: Vec<&str> = schemas.iter().map(|schema| schema.dataset.name.as_str()).collect();
+        assert_eq!(names, vec!["authors", "books", "reviews"]);
+        assert_eq!(schemas[2].dataset.columns[1].key_space, Some(KeySpace { start: 1, count: 50 }));
+        assert_eq!(schemas[2].dataset.columns[2].key_space, Some(KeySpace { start: 1, count: 100 }));
+    }
+
+    #[test]
+    fn order_bookstore_schema() {
+        let multi = MultiSchema::from_path("./schemas/schema_bookstore.yaml".to_string()).unwrap();
+        let names: Vec<String> = multi.ordered_schemas().unwrap().into_iter().map(|schema| schema.dataset.name).collect();
+        assert_eq!(names, vec!["authors", "suppliers", "customers", "books", "orders", "reviews", "roaring_fkey_books"]);
+    }
+
+    #[test]
+    fn resolve_relationship_columns() {
+        let multi = MultiSchema::from_path("./test_data/schema_relationship.yaml".to_string()).unwrap();
+        let schemas = multi.ordered_schemas().unwrap();
+        let order_ids = &schemas[2].dataset.columns[1];
+        assert_eq!(order_ids.key_space, Some(KeySpace { start: 100, count: 25 }));
+        assert_eq!(order_ids.cardinality, Some(Cardinality::Range { min: 0, max: 6 }));
+        assert_eq!(order_ids.seed, Some(multi.relationships.as_ref().unwrap()[0].seed(0)));
+
+        // The bitmap rows must line up with the owner rows
+        let mut short = multi.clone();
+        short.datasets[2].num_records = Some(39);
+        let err = short.ordered_schemas().unwrap_err();
+        assert_eq!(format!("{}", err), "Column roaring_fkey_books.order_ids uses relationship books_orders but dataset roaring_fkey_books does not have the owner's 40 rows");
+
+        let mut unknown = multi;
+        unknown.datasets[2].columns[1].relationship = Some("authors_books".to_string());
+        let err = unknown.ordered_schemas().unwrap_err();
+        assert_eq!(format!("{}", err), "Column roaring_fkey_books.order_ids uses unknown relationship authors_books");
+    }
+
+    #[test]
+    fn reject_multi_schema_cycles_and_unknown_datasets() {
+        let cyclic = MultiSchema::from(r#"name: cyclic
+datasets:
+    - name: a
+      num_records: 10
+      columns:
+          - {name: id, dtype: serial}
+          - {name: b_id, dtype: int, references: b.id}
+    - name: b
+      num_records: 10
+      columns:
+          - {name: id, dtype: serial}
+          - {name: a_id, dtype: int, references: a.id}
+"#).unwrap();
+        let err = cyclic.ordered_schemas().unwrap_err();
+        assert_eq!(format!("{}", err), "Foreign key cycle between datasets a, b");
+
+        let unknown = MultiSchema::from(r#"name: unknown
+datasets:
+    - name: a
+      num_records: 10
+      columns:
+          - {name: c_id, dtype: int, references: c.id}
+"#).unwrap();
+        let err = unknown.ordered_schemas().unwrap_err();
+        assert_eq!(format!("{}", err), "Column a.c_id references unknown dataset c");
+    }
+
+    #[test]
+    fn parse_defaults_for_the_dtype() {
+        let column = |column: &str| Schema::from(&format!("name: defaults\ndataset:\n    name: t\n    columns:\n        - {}\n", column))
+            .unwrap().dataset.columns.remove(0);
+        assert_eq!(column("{name: adult, dtype: boolean, default: 'false'}").default_value(), Ok(Some(DValue::Boolean(false))));
+        assert_eq!(column("{name: age, dtype: age, default: 18}").default_value(), Ok(Some(DValue::Int(18))));
+        assert_eq!(column("{name: score, dtype: double, default: 0.5}").default_value(), Ok(Some(DValue::Double(0.5))));
+        assert_eq!(column("{name: state, dtype: string, default: draft}").default_value(), Ok(Some(DValue::Str("draft".to_string()))));
+        assert_eq!(column("{name: on, dtype: date, format: '%Y-%m-%d', default: '2020-01-31'}").default_value(),
+                   Ok(Some(DValue::Date("2020-01-31".to_string()))));
+        assert_eq!(column("{name: tags, dtype: roaringbitmap, min: 1, max: 9, cardinality: 2, default: '{3,1,3}'}").default_value(),
+                   Ok(Some(DValue::IdSet(vec![1, 3]))));
+        assert_eq!(column("{name: tags, dtype: roaringbitmap, min: 1, max: 9, cardinality: 2, default: '{}'}").default_value(),
+                   Ok(Some(DValue::IdSet(vec![]))));
+        assert_eq!(column("{name: nickname, dtype: name}").default_value(), Ok(None));
+        assert!(column("{name: on, dtype: date, format: '%Y-%m-%d', default: '31/01/2020'}").default_value().is_err());
+        assert!(column("{name: tags, dtype: roaringbitmap, default: '{1,-2}'}").default_value().is_err());
+    }
+
+    #[test]
+    fn reject_default_conflicts() {
+        let schema = |column: &str| Schema::from(&format!("name: defaults\ndataset:\n    name: t\n    columns:\n        - {}\n", column)).unwrap();
+        assert!(schema("{name: adult, dtype: boolean, default: 'false', default_ratio: 0.7, null_ratio: 0.3}").validate().is_ok());
+
+        let err = schema("{name: adult, dtype: boolean, default: maybe}").validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column t.adult has an invalid default \"maybe\": provided string was not `true` or `false` (line 5)");
+        let err = schema("{name: adult, dtype: boolean, default_ratio: 0.5}").validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column t.adult has default_ratio 0.5 but no default (line 5)");
+        let err = schema("{name: adult, dtype: boolean, default: 'false', default_ratio: 0.8, null_ratio: 0.3}").validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column t.adult has null_ratio 0.3 and default_ratio 0.8 adding up to more than 1 (line 5)");
+        let err = schema("{name: id, dtype: serial, default: 1, default_ratio: 0.1}").validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column t.id has default_ratio 0.1 but is a serial column (line 5)");
+    }
+
+    #[test]
+    fn reject_null_ratio_conflicts() {
+        let schema = |column: &str| Schema::from(&format!("name: nulls\ndataset:\n    name: t\n    columns:\n        - {}\n", column)).unwrap();
+        assert!(schema("{name: nickname, dtype: name, null_ratio: 0.3}").validate().is_ok());
+        assert!(schema("{name: id, dtype: serial, not_null: true, null_ratio: 0.0}").validate().is_ok());
+
+        let err = schema("{name: id, dtype: serial, not_null: true, null_ratio: 0.1}").validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column t.id has null_ratio 0.1 but not_null: true (line 5)");
+        let err = schema("{name: id, dtype: serial, primary_key: true, null_ratio: 0.1}").validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column t.id has null_ratio 0.1 but primary_key: true (line 5)");
+        let err = schema("{name: age, dtype: age, null_ratio: 1.5}").validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column t.age has null_ratio 1.5, expected a value between 0 and 1 (line 5)");
+    }
+
+    #[test]
+    fn report_every_problem_with_its_line() {
+        let schema = Schema::from(r#"name: orders_schema
+dataset:
+    name: orders
+    num_records: -1
+    columns:
+        - {name: id, dtype: serial, min: first}
+        - {name: placed_on, dtype: date, min: 2020-01-01, max: 2021-01-01}
+        - {name: amount, dtype: double, min: 10, max: 1}
+        - {name: status, dtype: string, one_of: []}
+        - {name: tags, dtype: roaringbitmap, min: 0, max: 4294967296}
+        - {name: id, dtype: int, mean: 1.0}
+"#).unwrap();
+        let err = schema.validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Schema orders_schema has 9 problems:
+Dataset orders has num_records -1, expected a positive count (line 3)
+Dataset orders has more than one column named id (line 3)
+Column orders.id has min \"first\", expected the integer first id (line 6)
+Column orders.placed_on is a date column without a format (line 7)
+Column orders.amount has min 10 not below max 1 (line 8)
+Column orders.status has an empty one_of (line 9)
+Column orders.tags has max 4294967296 beyond the 32-bit ids of a roaringbitmap, use roaringbitmap64 (line 10)
+Column orders.tags is a bitmap column without a cardinality (line 10)
+Column orders.id sets only one of mean and std (line 11)");
+    }
+
+    #[test]
+    fn reject_references_without_keys_to_draw() {
+        let multi_schema = MultiSchema::from(r#"name: store_schema
+datasets:
+  - name: authors
+    num_records: 0
+    columns:
+      - {name: id, dtype: serial}
+  - name: books
+    num_records: 10
+    columns:
+      - {name: id, dtype: serial}
+      - {name: author_id, dtype: long, references: authors.id}
+      - {name: author_name, dtype: string, references: authors.id}
+"#).unwrap();
+        let err = multi_schema.validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Schema store_schema has 3 problems:
+Column books.author_name has references, which string columns do not take, expected int or long (line 12)
+Column books.author_id references dataset authors without rows, so there is no key to draw (line 11)
+Column books.author_name references dataset authors without rows, so there is no key to draw (line 12)");
+
+        let parent = Schema { name: "authors_schema".to_string(), seed: None, dataset: multi_schema.datasets[0].clone() };
+        let mut child = Schema { name: "books_schema".to_string(), seed: None, dataset: multi_schema.datasets[1].clone() };
+        child.dataset.columns.truncate(2);
+        child.resolve_references(&[parent]).unwrap();
+        let err = child.validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column books.author_id references a dataset without rows, so there is no key to draw (line 11)");
+    }
+
+    #[test]
+    fn locate_columns_declared_before_the_dataset_name() {
+        let schema = Schema::from(r#"name: person_schema
+dataset:
+  columns:
+    - {name: id, dtype: serial}
+    -
+      dtype: string
+      name: name
+  name: person_table
+"#).unwrap();
+        assert_eq!(schema.dataset.line, Some(8));
+        assert_eq!(schema.dataset.columns.iter().map(|column| column.line).collect::<Vec<_>>(), vec![Some(4), Some(7)]);
+    }
+
+    #[test]
+    fn locate_columns_past_a_commented_out_column() {
+        let multi_schema = MultiSchema::from(r#"name: store_schema
+datasets:
+  - name: books
+    num_records: 10
+    columns:
+      - {name: id, dtype: serial}
+      # - {name: title, dtype: string}
+      - {name: title, dtype: string, min: 1, max: 2}
+"#).unwrap();
+        assert_eq!(multi_schema.datasets[0].columns.iter().map(|column| column.line).collect::<Vec<_>>(), vec![Some(6), Some(8)]);
+        let err = multi_schema.validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Column books.title has min or max, which string columns do not take (line 8)");
+    }
+
+    #[test]
+    fn reject_int_references_beyond_32_bits() {
+        let multi_schema = MultiSchema::from(r#"name: store_schema
+datasets:
+  - name: authors
+    num_records: 100
+    columns:
+      - {name: id, dtype: serial, min: 2147483600}
+  - name: books
+    num_records: 10
+    columns:
+      - {name: id, dtype: serial}
+      - {name: author_id, dtype: int, references: authors.id}
+      - {name: author_key, dtype: long, references: authors.id}
+"#).unwrap();
+        let err = multi_schema.ordered_schemas().unwrap_err();
+        assert_eq!(format!("{}", err), "Column books.author_id references keys 2147483600 to 2147483699, beyond the 32-bit values of an int column, use long (line 11)");
+    }
+
+    #[test]
+    fn reject_bitmap_key_spaces_out_of_range() {
+        let multi_schema = MultiSchema::from(r#"name: store_schema
+relationships:
+  - {name: books_orders, owner: books.id, target: orders.id, cardinality: 2}
+datasets:
+  - name: orders
+    num_records: 100
+    columns:
+      - {name: id, dtype: serial, min: 2147483600}
+  - name: refunds
+    num_records: 10
+    columns:
+      - {name: id, dtype: serial, min: -5}
+  - name: books
+    num_records: 10
+    columns:
+      - {name: id, dtype: serial}
+      - {name: order_ids, dtype: roaringbitmap, references: orders.id, cardinality: 2}
+      - {name: order_ids64, dtype: roaringbitmap64, references: orders.id, cardinality: 2}
+      - {name: refund_ids, dtype: roaringbitmap64, references: refunds.id, cardinality: 2}
+      - {name: related_orders, dtype: roaringbitmap, relationship: books_orders}
+"#).unwrap();
+        let err = multi_schema.ordered_schemas().unwrap_err();
+        assert_eq!(format!("{}", err), "Schema store_schema has 3 problems:
+Column books.order_ids draws ids up to 2147483699, beyond the 32-bit ids of a roaringbitmap, use roaringbitmap64 (line 17)
+Column books.refund_ids draws ids from -5 on, but bitmap ids cannot be negative (line 19)
+Column books.related_orders draws ids up to 2147483699, beyond the 32-bit ids of a roaringbitmap, use roaringbitmap64 (line 20)");
+    }
+
+    #[test]
+    fn report_relationship_problems_with_their_line() {
+        let multi_schema = MultiSchema::from(r#"name: store_schema
+relationships:
+  - name: books_orders
+    owner: books.id
+    target: orders.id
+    cardinality: {min: 3, max: 1}
+  - name: books_authors
+    owner: books
+    target: books.id
+    cardinality: 2
+    join_table: {name: book_authors, owner_column: book_id, target_column: author_id, delimiter: "||"}
+datasets:
+  - name: books
+    num_records: 10
+    columns:
+      - {name: id, dtype: serial}
+"#).unwrap();
+        let err = multi_schema.validate().unwrap_err();
+        assert_eq!(format!("{}", err), "Schema store_schema has 4 problems:
+Relationship books_orders references unknown dataset orders (line 3)
+Relationship books_orders has a cardinality from 3 to 1, expected min up to max (line 3)
+Relationship books_authors has an invalid key books, expected <dataset>.<column> (line 7)
+Relationship books_authors has join table delimiter \"||\", expected a single character (line 7)");
+    }
+
+    #[test]
+    fn reject_unknown_keys_and_dtypes() {
+        let err = Schema::from("name: s\ndataset:\n    name: t\n    columns:\n        - {name: id, dtype: id}\n").unwrap_err();
+        assert!(format!("{}", err).starts_with("dataset.columns[0].dtype: unknown variant `id`"), "{}", err);
+        assert!(format!("{}", err).ends_with("at line 5 column 29"), "{}", err);
+
+        let err = Schema::from("name: s\ndataset:\n    name: t\n    columns:\n        - {name: id, dtype: int, nullable: true}\n").unwrap_err();
+        assert!(format!("{}", err).starts_with("dataset.columns[0]: unknown field `nullable`"), "{}", err);
+
+        let err = Schema::from_path("./test_data/missing.yaml".to_string()).unwrap_err();
+        assert!(format!("{}", err).starts_with("Unable to read the schema file ./test_data/missing.yaml"), "{}", err);
+    }
+
+    #[test]
+    fn validate_the_shipped_schemas() {
+        for dir in &["./test_data", "./schemas"] {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_none_or(|extension| extension != "yaml") {
+                    continue;
+                }
+                let yaml = std::fs::read_to_string(&path).unwrap();
+                let validated = if yaml.contains("\ndatasets:") {
+                    MultiSchema::from(&yaml).and_then(|multi_schema| multi_schema.validate())
+                } else {
+                    Schema::from(&yaml).and_then(|schema| schema.validate())
+                };
+                assert!(validated.is_ok(), "{}: {}", path.display(), validated.unwrap_err());
+            }
+        }
     }
 }
@@ -1,50 +1,363 @@
 use crate::errors::DataGenError::WeirdCase;
 use crate::errors::*;
-use crate::schema::Schema;
-use crate::sinks::Sink;
+use crate::fakegen::iso_date;
+use crate::schema::{Column, Schema};
+use crate::sinks::{Batch, ColumnValues, Sink};
 use crate::DValue;
-use std::io::Write;
+use std::fmt::{Display, Write as _};
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
 
-pub struct CSVSink<W: Write>(Schema, csv::Writer<W>);
+/// Fields written between quotes
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuoteStyle {
+    Always,
+    /// Fields holding the delimiter, a quote, the escape or a line break, and fields reading as the NULL token
+    Necessary,
+    /// Necessary fields and every field that does not read as a number
+    NonNumeric,
+    Never,
+}
+
+impl FromStr for QuoteStyle {
+    type Err = DataGenError;
+
+    fn from_str(s: &str) -> DataGenResult<QuoteStyle> {
+        match s {
+            "always" => Ok(QuoteStyle::Always),
+            "necessary" => Ok(QuoteStyle::Necessary),
+            "non-numeric" => Ok(QuoteStyle::NonNumeric),
+            "never" => Ok(QuoteStyle::Never),
+            _ => Err(WeirdCase { message: format!("Unknown quote style {}, expected always, necessary, non-numeric or never", s) }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Terminator {
+    Lf,
+    Crlf,
+}
+
+impl FromStr for Terminator {
+    type Err = DataGenError;
+
+    fn from_str(s: &str) -> DataGenResult<Terminator> {
+        match s {
+            "lf" => Ok(Terminator::Lf),
+            "crlf" => Ok(Terminator::Crlf),
+            _ => Err(WeirdCase { message: format!("Unknown line terminator {}, expected lf or crlf", s) }),
+        }
+    }
+}
+
+impl Terminator {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Terminator::Lf => b"\n",
+            Terminator::Crlf => b"\r\n",
+        }
+    }
+}
 
-pub fn sink<W: Write>(schema: Schema, w: W, delimiter: u8) -> DataGenResult<CSVSink<W>> {
-    Ok(CSVSink(
-        schema,
-        csv::WriterBuilder::new()
+/// Dialect of the CSV files. The delimiter is not part of it, as each dataset of a schema file has its own.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct CsvOptions {
+    /// Writes the column names as the first row of every file
+    pub header: bool,
+    pub quote_style: QuoteStyle,
+    pub quote: u8,
+    /// Escapes quotes inside quoted fields with this byte instead of doubling them
+    pub escape: Option<u8>,
+    pub terminator: Terminator,
+    /// Written unquoted for NULL values
+    pub null: String,
+    /// Writes every value the way Postgres `COPY ... WITH (FORMAT csv)` reads it back: only the fields that
+    /// need it are quoted, whatever `quote_style`, non-finite floats are spelled `NaN`, `Infinity` and
+    /// `-Infinity`, and dates and datetimes are written in ISO 8601 whatever the column format
+    pub postgres: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            header: false,
+            quote_style: QuoteStyle::NonNumeric,
+            quote: b'"',
+            escape: None,
+            terminator: Terminator::Lf,
+            null: String::new(),
+            postgres: false,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Rejects dialects whose files could not be read back unambiguously with `delimiter`
+    pub fn validate(&self, delimiter: u8) -> DataGenResult<()> {
+        let special = |b: u8| b == b'\r' || b == b'\n';
+        if self.quote == delimiter || self.escape == Some(delimiter) || special(self.quote) || self.escape.is_some_and(special) {
+            return Err(WeirdCase { message: "The quote and escape characters must differ from the delimiter and line breaks".to_string() });
+        }
+        if self.null.bytes().any(|b| b == delimiter || b == self.quote || special(b)) {
+            return Err(WeirdCase { message: format!("The NULL token {:?} must not hold the delimiter, the quote or line breaks", self.null) });
+        }
+        Ok(())
+    }
+
+    /// Reader of the files written with this dialect and `delimiter`, skipping the header row
+    pub fn reader(&self, delimiter: u8) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .has_headers(self.header)
+            .flexible(true)
             .delimiter(delimiter)
-            .quote_style(csv::QuoteStyle::NonNumeric)
-            .from_writer(w),
-    ))
+            .quote(self.quote)
+            .escape(self.escape)
+            .double_quote(self.escape.is_none());
+        builder
+    }
+}
+
+pub struct CSVSink<W: Write> {
+    /// Columns of the schema, whose formats postgres mode parses dates with
+    columns: Vec<Column>,
+    options: CsvOptions,
+    delimiter: u8,
+    /// Bytes of the row being written
+    line: Vec<u8>,
+    /// Text of the batch field being written, reused across fields
+    field: String,
+    writer: BufWriter<W>,
+}
+
+/// Writes one row per record, preceded by the column names of `schema` when `options.header` is set
+pub fn sink<W: Write>(schema: Schema, w: W, delimiter: u8, options: CsvOptions) -> DataGenResult<CSVSink<W>> {
+    options.validate(delimiter)?;
+    let columns = schema.dataset.columns;
+    let mut sink = CSVSink { columns, options, delimiter, line: Vec::new(), field: String::new(), writer: BufWriter::new(w) };
+    if sink.options.header {
+        let names: Vec<Option<String>> = sink.columns.iter().map(|column| Some(column.name.clone())).collect();
+        sink.write_row(&names)?;
+    }
+    Ok(sink)
+}
+
+impl<W: Write> CSVSink<W> {
+    /// Writes a row of fields, `None` standing for NULL
+    fn write_row(&mut self, fields: &[Option<String>]) -> DataGenResult<()> {
+        self.line.clear();
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                self.line.push(self.delimiter);
+            }
+            self.push_field(field.as_deref());
+        }
+        self.end_row()
+    }
+
+    fn end_row(&mut self) -> DataGenResult<()> {
+        self.line.extend_from_slice(self.options.terminator.as_bytes());
+        self.writer.write_all(&self.line)?;
+        Ok(())
+    }
+
+    /// Pushes a field onto the row, quoted if need be, `None` standing for NULL
+    fn push_field(&mut self, field: Option<&str>) {
+        match field {
+            None => self.line.extend_from_slice(self.options.null.as_bytes()),
+            Some(field) if self.should_quote(field) => self.push_quoted(field),
+            Some(field) => self.line.extend_from_slice(field.as_bytes()),
+        }
+    }
+
+    /// Pushes the field `format` writes, written into the reused `field` buffer rather than a new string
+    fn push_formatted(&mut self, format: impl FnOnce(&mut String)) {
+        let mut field = std::mem::take(&mut self.field);
+        field.clear();
+        format(&mut field);
+        self.push_field(Some(&field));
+        self.field = field;
+    }
+
+    /// Text of the value of the `index`th column, see `dvalue_to_csv`
+    fn field_text(&self, index: usize, value: DValue) -> DataGenResult<Option<String>> {
+        match value {
+            DValue::Date(val) | DValue::DateTime(val) if self.options.postgres => Ok(Some(iso_date(self.column(index)?, &val)?)),
+            value => Ok(dvalue_to_csv(value, self.options.postgres)),
+        }
+    }
+
+    fn column(&self, index: usize) -> DataGenResult<&Column> {
+        self.columns.get(index).ok_or_else(|| WeirdCase {
+            message: format!("The record has more fields than the {} columns of the schema", self.columns.len())
+        })
+    }
+
+    /// Pushes the field of `row` of the `index`th column, the same text `field_text` gives its value
+    fn push_value(&mut self, index: usize, column: &ColumnValues, row: usize) -> DataGenResult<()> {
+        let postgres = self.options.postgres;
+        match column {
+            ColumnValues::Boolean(values) => self.push_display(values[row]),
+            ColumnValues::Int(values) => self.push_display(values[row]),
+            ColumnValues::Long(values) => self.push_display(values[row]),
+            ColumnValues::Float(values) => match values[row] {
+                Some(val) if postgres && !val.is_finite() => self.push_field(Some(postgres_non_finite(val as f64))),
+                val => self.push_display(val),
+            },
+            ColumnValues::Double(values) => match values[row] {
+                Some(val) if postgres && !val.is_finite() => self.push_field(Some(postgres_non_finite(val))),
+                val => self.push_display(val),
+            },
+            ColumnValues::Str(values) => self.push_field(values[row].as_deref()),
+            ColumnValues::Date(values) | ColumnValues::DateTime(values) => match &values[row] {
+                Some(val) if postgres => {
+                    let iso = iso_date(self.column(index)?, val)?;
+                    self.push_field(Some(&iso))
+                }
+                val => self.push_field(val.as_deref()),
+            },
+            ColumnValues::Bytes(values) => match &values[row] {
+                Some(bytes) => self.push_formatted(|field| push_bytea_hex(field, bytes)),
+                None => self.push_field(None),
+            },
+            ColumnValues::IdSet(values) => match &values[row] {
+                Some(ids) => self.push_formatted(|field| push_id_set(field, ids)),
+                None => self.push_field(None),
+            },
+        }
+        Ok(())
+    }
+
+    fn push_display<T: Display>(&mut self, value: Option<T>) {
+        match value {
+            Some(value) => self.push_formatted(|field| write!(field, "{}", value).unwrap()),
+            None => self.push_field(None),
+        }
+    }
+
+    fn should_quote(&self, field: &str) -> bool {
+        let options = &self.options;
+        // A value reading as the NULL token, or as the `\.` end-of-data marker of COPY, must stay a value
+        let necessary = field == options.null
+            || field == "\\."
+            || field.bytes().any(|b| b == self.delimiter || b == options.quote || Some(b) == options.escape || b == b'\r' || b == b'\n');
+        let style = if options.postgres { QuoteStyle::Necessary } else { options.quote_style };
+        match style {
+            QuoteStyle::Always => true,
+            QuoteStyle::Necessary => necessary,
+            QuoteStyle::NonNumeric => necessary || (field.parse::<f64>().is_err() && field.parse::<i128>().is_err()),
+            QuoteStyle::Never => false,
+        }
+    }
+
+    fn push_quoted(&mut self, field: &str) {
+        let quote = self.options.quote;
+        self.line.push(quote);
+        for b in field.bytes() {
+            match self.options.escape {
+                Some(escape) if b == quote || b == escape => self.line.push(escape),
+                None if b == quote => self.line.push(quote),
+                _ => {}
+            }
+            self.line.push(b);
+        }
+        self.line.push(quote);
+    }
 }
 
 impl<W: Write> Sink for CSVSink<W> {
     fn write(&mut self, value: DValue) -> Result<(), DataGenError> {
         match value {
             DValue::Record(vec) => {
-                let rec: Vec<String> = vec.into_iter().map(|(_key, value)| dvalue_to_csv(value)).collect();
-                self.1.write_record(rec)?;
-
-                Ok(())
+                let rec = vec.into_iter().enumerate()
+                    .map(|(index, (_key, value))| self.field_text(index, value))
+                    .collect::<DataGenResult<Vec<Option<String>>>>()?;
+                self.write_row(&rec)
             }
             _ => Err(WeirdCase { message: format!("The 'value' parameters received at the CSVSink is not a Record. Value found was : {:?}", value) })
         }
     }
+
+    /// Serializes the columns straight into the row buffer, with no string per field
+    fn write_batch(&mut self, batch: Batch) -> DataGenResult<()> {
+        for row in 0..batch.num_rows {
+            self.line.clear();
+            for (i, column) in batch.columns.iter().enumerate() {
+                if i > 0 {
+                    self.line.push(self.delimiter);
+                }
+                self.push_value(i, column, row)?;
+            }
+            self.end_row()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> DataGenResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
 }
 
+/// Text of a value, `None` for NULL. With `postgres`, non-finite floats are spelled the way Postgres reads them.
 #[rustfmt::skip]
-fn dvalue_to_csv(value: DValue) -> String {
+fn dvalue_to_csv(value: DValue, postgres: bool) -> Option<String> {
     use DValue::*;
-    match value {
-        Boolean(val)   => val.to_string(),
+    let text = match value {
+        Null            => return None,
+        Boolean(val)    => val.to_string(),
         Int(val)        => val.to_string(),
         Long(val)       => val.to_string(),
+        Float(val) if postgres && !val.is_finite()  => postgres_non_finite(val as f64).to_string(),
+        Double(val) if postgres && !val.is_finite() => postgres_non_finite(val).to_string(),
         Float(val)      => val.to_string(),
         Double(val)     => val.to_string(),
-        Bytes(val)  => format!("{:?}", val),
-        Str(val)      => val.to_string(),
-        Date(val)     => val.to_string(),
-        DateTime(val) => val.to_string(),
-        x            => format!("{:?}", x)
+        Bytes(val)      => bytea_hex(&val),
+        Str(val)        => val,
+        Date(val)       => val,
+        DateTime(val)   => val,
+        IdSet(val)      => { let mut text = String::new(); push_id_set(&mut text, &val); text }
+        x               => format!("{:?}", x)
+    };
+    Some(text)
+}
+
+fn postgres_non_finite(val: f64) -> &'static str {
+    if val.is_nan() {
+        "NaN"
+    } else if val > 0.0 {
+        "Infinity"
+    } else {
+        "-Infinity"
+    }
+}
+
+/// Array literal of a bitmap's ids, e.g. `{1,2,3}`
+fn push_id_set(text: &mut String, ids: &[i64]) {
+    text.push('{');
+    for (i, id) in ids.iter().enumerate() {
+        if i > 0 {
+            text.push(',');
+        }
+        write!(text, "{}", id).unwrap();
+    }
+    text.push('}');
+}
+
+/// Postgres `bytea` hex format, e.g. `\x3a30`
+pub(crate) fn bytea_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + bytes.len() * 2);
+    push_bytea_hex(&mut hex, bytes);
+    hex
+}
+
+fn push_bytea_hex(hex: &mut String, bytes: &[u8]) {
+    hex.push_str("\\x");
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
     }
 }
 
@@ -66,12 +379,190 @@ mod tests {
 
         let mut vec: Vec<u8> = Vec::new();
         {
-            let mut sink = sink(schema, &mut vec, ',' as u8).unwrap();
+            let mut sink = sink(schema, &mut vec, b',', CsvOptions::default()).unwrap();
             sink.write(record).unwrap();
+            sink.finish().unwrap();
         }
         pretty_assertions::assert_eq!(
             "1,\"Jason\",90,\"true\",\"Male\"\n".as_bytes(),
             vec.as_slice()
         );
     }
+
+    #[test]
+    fn generate_csv_id_set_as_array_or_bytea_literal() {
+        let record = DValue::Record(vec![
+            ("id".to_string(), DValue::Long(1)),
+            ("book_ids".to_string(), DValue::IdSet(vec![3, 5, 8])),
+            ("review_ids".to_string(), DValue::IdSet(vec![])),
+            ("tag_ids".to_string(), DValue::Bytes(vec![0x3a, 0x30, 0x00, 0xff])),
+        ]);
+
+        let schema = Schema::from_path("./test_data/schema_simple.yaml".to_string()).unwrap();
+
+        let mut vec: Vec<u8> = Vec::new();
+        {
+            let mut sink = sink(schema, &mut vec, b'|', CsvOptions::default()).unwrap();
+            sink.write(record).unwrap();
+            sink.finish().unwrap();
+        }
+        pretty_assertions::assert_eq!(
+            "1|\"{3,5,8}\"|\"{}\"|\"\\x3a3000ff\"\n".as_bytes(),
+            vec.as_slice()
+        );
+    }
+
+    #[test]
+    fn generate_csv_with_header_null_token_and_postgres_quoting() {
+        let record = |name: &str, price: DValue| DValue::Record(vec![
+            ("id".to_string(), DValue::Int(1)),
+            ("name".to_string(), DValue::Str(name.to_string())),
+            ("age".to_string(), DValue::Null),
+            ("adult".to_string(), DValue::Boolean(false)),
+            ("price".to_string(), price),
+        ]);
+        let schema = Schema::from(r#"name: quoting_schema
+dataset:
+  name: quoting_table
+  columns:
+    - {name: id, dtype: int}
+    - {name: name, dtype: string}
+    - {name: age, dtype: int}
+    - {name: adult, dtype: boolean}
+    - {name: price, dtype: double}
+"#).unwrap();
+        let options = CsvOptions { header: true, terminator: Terminator::Crlf, null: "\\N".to_string(), postgres: true, ..CsvOptions::default() };
+
+        let mut vec: Vec<u8> = Vec::new();
+        {
+            let mut sink = sink(schema.clone(), &mut vec, b',', options).unwrap();
+            sink.write(record("Jason", DValue::Double(f64::NEG_INFINITY))).unwrap();
+            sink.write(record("\\N", DValue::Float(f32::NAN))).unwrap();
+            sink.write(record("say \"hi\", then\nleave", DValue::Double(1.5))).unwrap();
+            sink.finish().unwrap();
+        }
+        pretty_assertions::assert_eq!(
+            "id,name,age,adult,price\r\n1,Jason,\\N,false,-Infinity\r\n1,\"\\N\",\\N,false,NaN\r\n1,\"say \"\"hi\"\", then\nleave\",\\N,false,1.5\r\n",
+            String::from_utf8(vec).unwrap()
+        );
+
+        let mut vec: Vec<u8> = Vec::new();
+        {
+            let options = CsvOptions { quote_style: QuoteStyle::Always, escape: Some(b'\\'), ..CsvOptions::default() };
+            let mut sink = sink(schema.clone(), &mut vec, b'|', options).unwrap();
+            sink.write(record("a\\\"b", DValue::Str(String::new()))).unwrap();
+            sink.finish().unwrap();
+        }
+        pretty_assertions::assert_eq!("\"1\"|\"a\\\\\\\"b\"||\"false\"|\"\"\n", String::from_utf8(vec).unwrap());
+
+        assert!(sink(schema.clone(), Vec::new(), b',', CsvOptions { quote: b',', ..CsvOptions::default() }).is_err());
+        assert!(sink(schema, Vec::new(), b'|', CsvOptions { null: "N|A".to_string(), ..CsvOptions::default() }).is_err());
+    }
+
+    #[test]
+    fn generate_csv_dates_in_iso_8601_for_postgres() {
+        let schema = Schema::from(r#"name: dates_schema
+dataset:
+  name: dates_table
+  columns:
+    - {name: dob, dtype: date, format: "%d/%m/%Y"}
+    - {name: seen_at, dtype: datetime, format: "%d/%m/%Y %H:%M:%S"}
+    - {name: picked_at, dtype: datetime, format: "%m/%d/%Y %I:%M %p", one_of: ["11/28/2014 01:05 PM"]}
+"#).unwrap();
+        let batch = Batch {
+            names: vec!["dob".to_string(), "seen_at".to_string(), "picked_at".to_string()],
+            columns: vec![
+                ColumnValues::Date(vec![Some("02/01/2014".to_string()), None]),
+                ColumnValues::DateTime(vec![Some("28/11/2014 12:00:09".to_string()), None]),
+                ColumnValues::Date(vec![Some("11/28/2014 01:05 PM".to_string()), None]),
+            ],
+            num_rows: 2,
+        };
+
+        for postgres in &[false, true] {
+            let options = CsvOptions { postgres: *postgres, ..CsvOptions::default() };
+            let mut by_batch: Vec<u8> = Vec::new();
+            let mut by_record: Vec<u8> = Vec::new();
+            {
+                let mut sink = sink(schema.clone(), &mut by_batch, b',', options.clone()).unwrap();
+                sink.write_batch(batch.clone()).unwrap();
+                sink.finish().unwrap();
+            }
+            {
+                let mut sink = sink(schema.clone(), &mut by_record, b',', options.clone()).unwrap();
+                for record in batch.clone().into_records() {
+                    sink.write(record).unwrap();
+                }
+                sink.finish().unwrap();
+            }
+            let expected = if *postgres {
+                "2014-01-02,2014-11-28 12:00:09,2014-11-28 13:05:00\n,,\n"
+            } else {
+                "\"02/01/2014\",\"28/11/2014 12:00:09\",\"11/28/2014 01:05 PM\"\n,,\n"
+            };
+            pretty_assertions::assert_eq!(String::from_utf8(by_batch).unwrap(), expected);
+            pretty_assertions::assert_eq!(String::from_utf8(by_record).unwrap(), expected);
+        }
+
+        let mut vec: Vec<u8> = Vec::new();
+        let mut sink = sink(schema, &mut vec, b',', CsvOptions { postgres: true, ..CsvOptions::default() }).unwrap();
+        assert!(sink.write(DValue::Record(vec![("dob".to_string(), DValue::Date("2014-01-02".to_string()))])).is_err());
+    }
+
+    #[test]
+    fn write_batch_as_its_records() {
+        let batch = Batch {
+            names: ["adult", "age", "id", "price", "score", "name", "dob", "seen_at", "tags", "book_ids"].iter().map(|name| name.to_string()).collect(),
+            columns: vec![
+                ColumnValues::Boolean(vec![Some(true), None, Some(false)]),
+                ColumnValues::Int(vec![Some(-4), Some(90), None]),
+                ColumnValues::Long(vec![None, Some(1 << 40), Some(0)]),
+                ColumnValues::Float(vec![Some(1.5), Some(f32::NAN), None]),
+                ColumnValues::Double(vec![Some(f64::NEG_INFINITY), None, Some(0.1)]),
+                ColumnValues::Str(vec![Some("say \"hi\", then\nleave".to_string()), Some("\\N".to_string()), None]),
+                ColumnValues::Date(vec![Some("02/01/2014".to_string()), None, Some("31/12/1999".to_string())]),
+                ColumnValues::DateTime(vec![None, Some("28/11/2014 12:00:09".to_string()), Some("01/01/2000 00:00:00".to_string())]),
+                ColumnValues::Bytes(vec![Some(vec![0x3a, 0x30, 0x00, 0xff]), Some(vec![]), None]),
+                ColumnValues::IdSet(vec![Some(vec![3, 5, 8]), None, Some(vec![])]),
+            ],
+            num_rows: 3,
+        };
+        let schema = Schema::from(r#"name: batch_schema
+dataset:
+  name: batch_table
+  columns:
+    - {name: adult, dtype: boolean}
+    - {name: age, dtype: int}
+    - {name: id, dtype: long}
+    - {name: price, dtype: float}
+    - {name: score, dtype: double}
+    - {name: name, dtype: string}
+    - {name: dob, dtype: date, format: "%d/%m/%Y"}
+    - {name: seen_at, dtype: datetime, format: "%d/%m/%Y %H:%M:%S"}
+    - {name: tags, dtype: roaringbitmap}
+    - {name: book_ids, dtype: long}
+"#).unwrap();
+        let options = [
+            CsvOptions::default(),
+            CsvOptions { header: true, null: "\\N".to_string(), postgres: true, ..CsvOptions::default() },
+            CsvOptions { quote_style: QuoteStyle::Always, escape: Some(b'\\'), terminator: Terminator::Crlf, ..CsvOptions::default() },
+        ];
+        for options in &options {
+            let mut by_batch: Vec<u8> = Vec::new();
+            let mut by_record: Vec<u8> = Vec::new();
+            {
+                let mut sink = sink(schema.clone(), &mut by_batch, b',', options.clone()).unwrap();
+                sink.write_batch(batch.clone()).unwrap();
+                sink.finish().unwrap();
+            }
+            {
+                let mut sink = sink(schema.clone(), &mut by_record, b',', options.clone()).unwrap();
+                for record in batch.clone().into_records() {
+                    sink.write(record).unwrap();
+                }
+                sink.finish().unwrap();
+            }
+            pretty_assertions::assert_eq!(String::from_utf8(by_batch).unwrap(), String::from_utf8(by_record).unwrap());
+        }
+    }
 }
@@ -0,0 +1,84 @@
+use crate::errors::DataGenError::WeirdCase;
+use crate::errors::*;
+use crate::sinks::Sink;
+use crate::DValue;
+use std::io::Write;
+
+/// Writes one JSON object per line (NDJSON), keyed by column name in column order
+pub struct JsonSink<W: Write>(W);
+
+pub fn sink<W: Write>(w: W) -> DataGenResult<JsonSink<W>> {
+    Ok(JsonSink(w))
+}
+
+impl<W: Write> Sink for JsonSink<W> {
+    fn write(&mut self, value: DValue) -> Result<(), DataGenError> {
+        match value {
+            DValue::Record(_) => {
+                let mut line = dvalue_to_json(&value).to_string();
+                line.push('\n');
+                self.0.write_all(line.as_bytes())?;
+                Ok(())
+            }
+            _ => Err(WeirdCase { message: format!("The 'value' parameters received at the JsonSink is not a Record. Value found was : {:?}", value) })
+        }
+    }
+
+    fn finish(&mut self) -> DataGenResult<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// JSON value of a generated value: native numbers and booleans, `null`, arrays for id sets and
+/// objects for records. Non-finite floats have no JSON representation and become `null`.
+#[rustfmt::skip]
+fn dvalue_to_json(value: &DValue) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        DValue::Null            => Value::Null,
+        DValue::Boolean(val)    => Value::from(*val),
+        DValue::Int(val)        => Value::from(*val),
+        DValue::Long(val)       => Value::from(*val),
+        DValue::Float(val)      => Value::from(*val),
+        DValue::Double(val)     => Value::from(*val),
+        DValue::Bytes(val)      => Value::from(crate::sinks::csv_sink::bytea_hex(val)),
+        DValue::Str(val)        => Value::from(val.as_str()),
+        DValue::Date(val)       => Value::from(val.as_str()),
+        DValue::DateTime(val)   => Value::from(val.as_str()),
+        DValue::IdSet(val)      => Value::from(val.clone()),
+        DValue::Record(fields)  => Value::Object(fields.iter().map(|(key, value)| (key.clone(), dvalue_to_json(value))).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_json_line_from_record() {
+        let record = DValue::Record(vec![
+            ("id".to_string(), DValue::Long(1)),
+            ("name".to_string(), DValue::Str("Jason \"J\"".to_string())),
+            ("score".to_string(), DValue::Double(1.5)),
+            ("adult".to_string(), DValue::Boolean(true)),
+            ("nickname".to_string(), DValue::Null),
+            ("book_ids".to_string(), DValue::IdSet(vec![3, 5])),
+            ("address".to_string(), DValue::Record(vec![
+                ("city".to_string(), DValue::Str("Paris".to_string())),
+                ("zip".to_string(), DValue::Int(75001)),
+            ])),
+        ]);
+
+        let mut vec: Vec<u8> = Vec::new();
+        {
+            let mut sink = sink(&mut vec).unwrap();
+            sink.write(record).unwrap();
+            sink.finish().unwrap();
+        }
+        pretty_assertions::assert_eq!(
+            "{\"id\":1,\"name\":\"Jason \\\"J\\\"\",\"score\":1.5,\"adult\":true,\"nickname\":null,\"book_ids\":[3,5],\"address\":{\"city\":\"Paris\",\"zip\":75001}}\n",
+            String::from_utf8(vec).unwrap()
+        );
+    }
+}
@@ -1,8 +1,81 @@
 use crate::errors::DataGenResult;
 use crate::DValue;
 
+pub mod avro_sink;
 pub mod csv_sink;
+pub mod json_sink;
+pub mod pgcopy_sink;
+pub mod sql_sink;
 
 pub trait Sink {
     fn write(&mut self, value: DValue) -> DataGenResult<()>;
+
+    /// Writes rows generated column by column, see `RecordGenerator::generate_batch`.
+    /// Sinks without a columnar path write them record by record.
+    fn write_batch(&mut self, batch: Batch) -> DataGenResult<()> {
+        for record in batch.into_records() {
+            self.write(record)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out anything still buffered, e.g. the last block of a container format
+    fn finish(&mut self) -> DataGenResult<()> {
+        Ok(())
+    }
+}
+
+/// Consecutive rows of a dataset, held as one typed vector per column
+#[derive(Debug, PartialEq, Clone)]
+pub struct Batch {
+    pub names: Vec<String>,
+    pub columns: Vec<ColumnValues>,
+    pub num_rows: usize,
+}
+
+/// Values of one column of a batch, `None` standing for NULL
+#[derive(Debug, PartialEq, Clone)]
+pub enum ColumnValues {
+    Boolean(Vec<Option<bool>>),
+    Int(Vec<Option<i32>>),
+    Long(Vec<Option<i64>>),
+    Float(Vec<Option<f32>>),
+    Double(Vec<Option<f64>>),
+    Str(Vec<Option<String>>),
+    Date(Vec<Option<String>>),
+    DateTime(Vec<Option<String>>),
+    Bytes(Vec<Option<Vec<u8>>>),
+    IdSet(Vec<Option<Vec<i64>>>),
+}
+
+impl Batch {
+    /// The rows as records, the ones `RecordGenerator::generate` makes but for `one_of` datetimes,
+    /// which come out as `DValue::DateTime`
+    pub fn into_records(self) -> impl Iterator<Item = DValue> {
+        let names = self.names;
+        let mut columns: Vec<_> = self.columns.into_iter().map(ColumnValues::into_values).collect();
+        (0..self.num_rows).map(move |_| {
+            DValue::Record(names.iter().cloned().zip(columns.iter_mut().map(|values| values.next().unwrap_or(DValue::Null))).collect())
+        })
+    }
+}
+
+impl ColumnValues {
+    fn into_values(self) -> std::vec::IntoIter<DValue> {
+        fn values<T>(column: Vec<Option<T>>, value: fn(T) -> DValue) -> Vec<DValue> {
+            column.into_iter().map(|v| v.map_or(DValue::Null, value)).collect()
+        }
+        match self {
+            ColumnValues::Boolean(column) => values(column, DValue::Boolean),
+            ColumnValues::Int(column) => values(column, DValue::Int),
+            ColumnValues::Long(column) => values(column, DValue::Long),
+            ColumnValues::Float(column) => values(column, DValue::Float),
+            ColumnValues::Double(column) => values(column, DValue::Double),
+            ColumnValues::Str(column) => values(column, DValue::Str),
+            ColumnValues::Date(column) => values(column, DValue::Date),
+            ColumnValues::DateTime(column) => values(column, DValue::DateTime),
+            ColumnValues::Bytes(column) => values(column, DValue::Bytes),
+            ColumnValues::IdSet(column) => values(column, DValue::IdSet),
+        }.into_iter()
+    }
 }
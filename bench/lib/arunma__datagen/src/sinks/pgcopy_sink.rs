@@ -0,0 +1,200 @@
+use crate::errors::DataGenError::WeirdCase;
+use crate::errors::*;
+use crate::fakegen;
+use crate::schema::{Column, Schema};
+use crate::sinks::Sink;
+use crate::{DType, DValue};
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// Signature, flags field and header extension length of the binary COPY format
+const PGCOPY_HEADER: &[u8] = b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0";
+/// Days between the Unix epoch and the Postgres epoch, 2000-01-01
+const PG_EPOCH_DAYS: i32 = 10_957;
+const PG_EPOCH_MILLIS: i64 = PG_EPOCH_DAYS as i64 * 86_400_000;
+
+/// Writes the PostgreSQL `COPY ... FROM ... WITH (FORMAT binary)` format. Each value is encoded with the
+/// binary receive format of its column's Postgres type, e.g. `int4`, `int8`, `float4`, `float8`, `bool`,
+/// `text`, `date` and `timestamp`. Bitmap columns are sent in the portable roaring format read by
+/// `roaringbitmap` and `roaringbitmap64`, whatever their CSV `encoding`.
+pub struct PgCopySink<W: Write>(Schema, W);
+
+pub fn sink<W: Write>(schema: Schema, mut w: W) -> DataGenResult<PgCopySink<W>> {
+    w.write_all(PGCOPY_HEADER)?;
+    Ok(PgCopySink(schema, w))
+}
+
+impl<W: Write> Sink for PgCopySink<W> {
+    fn write(&mut self, value: DValue) -> Result<(), DataGenError> {
+        match value {
+            DValue::Record(vec) => {
+                let columns = &self.0.dataset.columns;
+                if vec.len() != columns.len() {
+                    return Err(WeirdCase {
+                        message: format!("Record of {} fields written to table {} of {} columns", vec.len(), self.0.dataset.name, columns.len())
+                    });
+                }
+                let field_count = i16::try_from(vec.len()).map_err(|_| WeirdCase {
+                    message: format!("Table {} has more columns than a COPY tuple holds", self.0.dataset.name)
+                })?;
+                let mut tuple = Vec::with_capacity(16 * vec.len());
+                tuple.extend_from_slice(&field_count.to_be_bytes());
+                for ((_key, value), column) in vec.into_iter().zip(columns.iter()) {
+                    match dvalue_to_pgcopy(column, value)? {
+                        Some(field) => {
+                            tuple.extend_from_slice(&(field.len() as i32).to_be_bytes());
+                            tuple.extend_from_slice(&field);
+                        }
+                        None => tuple.extend_from_slice(&(-1i32).to_be_bytes()),
+                    }
+                }
+                self.1.write_all(&tuple)?;
+                Ok(())
+            }
+            _ => Err(WeirdCase { message: format!("The 'value' parameters received at the PgCopySink is not a Record. Value found was : {:?}", value) })
+        }
+    }
+
+    fn finish(&mut self) -> DataGenResult<()> {
+        // File trailer: a field count of -1
+        self.1.write_all(&(-1i16).to_be_bytes())?;
+        self.1.flush()?;
+        Ok(())
+    }
+}
+
+/// Network-order binary encoding of a value, `None` for NULL
+#[rustfmt::skip]
+fn dvalue_to_pgcopy(column: &Column, value: DValue) -> DataGenResult<Option<Vec<u8>>> {
+    let field = match value {
+        DValue::Null            => return Ok(None),
+        DValue::Boolean(val)    => vec![val as u8],
+        DValue::Int(val)        => val.to_be_bytes().to_vec(),
+        DValue::Long(val)       => val.to_be_bytes().to_vec(),
+        DValue::Float(val)      => val.to_be_bytes().to_vec(),
+        DValue::Double(val)     => val.to_be_bytes().to_vec(),
+        DValue::Bytes(val)      => val,
+        DValue::Str(val)        => val.into_bytes(),
+        // `one_of` datetimes come out as `DValue::Date`, so the column type decides
+        DValue::Date(val) | DValue::DateTime(val) if column.dtype == DType::DateTime => {
+            let millis = fakegen::datetime_to_millis(column, &val).ok_or_else(|| WeirdCase {
+                message: format!("Column {} has datetime {} not matching its format", column.name, val)
+            })?;
+            // timestamp: microseconds since the Postgres epoch
+            ((millis - PG_EPOCH_MILLIS) * 1000).to_be_bytes().to_vec()
+        }
+        DValue::Date(val) | DValue::DateTime(val) => {
+            let days = fakegen::date_to_days(column, &val).ok_or_else(|| WeirdCase {
+                message: format!("Column {} has date {} not matching its format", column.name, val)
+            })?;
+            // date: days since the Postgres epoch
+            (days - PG_EPOCH_DAYS).to_be_bytes().to_vec()
+        }
+        DValue::IdSet(ids)      => fakegen::serialize_bitmap(&column.dtype, &ids),
+        DValue::Record(_)       => return Err(WeirdCase { message: format!("Column {} holds a nested record", column.name) }),
+    };
+    Ok(Some(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    /// Reads a big-endian integer of `N` bytes from the front of `bytes` and advances it
+    fn take<const N: usize>(bytes: &mut &[u8]) -> [u8; N] {
+        let (head, tail) = bytes.split_at(N);
+        *bytes = tail;
+        head.try_into().unwrap()
+    }
+
+    /// Decodes a binary COPY file into rows of fields, following the format spec:
+    /// header signature, flags, extension area, then tuples of length-prefixed fields and a -1 trailer
+    fn decode(mut bytes: &[u8]) -> Vec<Vec<Option<Vec<u8>>>> {
+        assert_eq!(&take::<11>(&mut bytes), b"PGCOPY\n\xff\r\n\0");
+        assert_eq!(i32::from_be_bytes(take(&mut bytes)), 0, "flags");
+        let extension = i32::from_be_bytes(take(&mut bytes));
+        bytes = &bytes[extension as usize..];
+
+        let mut rows = Vec::new();
+        loop {
+            let field_count = i16::from_be_bytes(take(&mut bytes));
+            if field_count == -1 {
+                assert!(bytes.is_empty(), "data after the trailer");
+                return rows;
+            }
+            let row = (0..field_count).map(|_| {
+                let length = i32::from_be_bytes(take(&mut bytes));
+                if length == -1 {
+                    return None;
+                }
+                let (field, rest) = bytes.split_at(length as usize);
+                bytes = rest;
+                Some(field.to_vec())
+            }).collect();
+            rows.push(row);
+        }
+    }
+
+    #[test]
+    fn write_pgcopy_binary_rows() {
+        let schema = Schema::from(r#"name: copy_schema
+dataset:
+  name: copy_table
+  columns:
+    - {name: id, dtype: long}
+    - {name: age, dtype: int}
+    - {name: adult, dtype: boolean}
+    - {name: weight, dtype: float}
+    - {name: score, dtype: double}
+    - {name: name, dtype: string}
+    - {name: dob, dtype: date, format: "%Y-%m-%d"}
+    - {name: seen_at, dtype: datetime, format: "%Y-%m-%d %H:%M:%S"}
+    - {name: book_ids, dtype: roaringbitmap, cardinality: 2, min: 1, max: 9}
+"#).unwrap();
+        let record = DValue::Record(vec![
+            ("id".to_string(), DValue::Long(-2)),
+            ("age".to_string(), DValue::Int(90)),
+            ("adult".to_string(), DValue::Boolean(true)),
+            ("weight".to_string(), DValue::Float(1.5)),
+            ("score".to_string(), DValue::Double(-0.25)),
+            ("name".to_string(), DValue::Str("Zoë".to_string())),
+            ("dob".to_string(), DValue::Date("2000-01-02".to_string())),
+            ("seen_at".to_string(), DValue::DateTime("1999-12-31 23:59:59".to_string())),
+            ("book_ids".to_string(), DValue::IdSet(vec![3, 5])),
+        ]);
+        let mut nulls = vec![("id".to_string(), DValue::Long(7))];
+        nulls.extend(schema.dataset.columns[1..].iter().map(|column| (column.name.clone(), DValue::Null)));
+        let nulls = DValue::Record(nulls);
+        let short = DValue::Record(vec![("id".to_string(), DValue::Long(8)), ("age".to_string(), DValue::Int(1))]);
+
+        let mut vec: Vec<u8> = Vec::new();
+        {
+            let mut sink = sink(schema, &mut vec).unwrap();
+            sink.write(record).unwrap();
+            sink.write(nulls).unwrap();
+            // A record missing fields is rejected rather than written as a short tuple
+            assert_eq!(sink.write(short).unwrap_err().to_string(), "Record of 2 fields written to table copy_table of 9 columns");
+            sink.finish().unwrap();
+        }
+
+        let rows = decode(&vec);
+        assert_eq!(rows.len(), 2);
+        let row = &rows[0];
+        assert_eq!(row.len(), 9);
+        let field = |i: usize| row[i].clone().unwrap();
+        assert_eq!(i64::from_be_bytes(field(0).try_into().unwrap()), -2);
+        assert_eq!(i32::from_be_bytes(field(1).try_into().unwrap()), 90);
+        assert_eq!(field(2), vec![1]);
+        assert_eq!(f32::from_be_bytes(field(3).try_into().unwrap()), 1.5);
+        assert_eq!(f64::from_be_bytes(field(4).try_into().unwrap()), -0.25);
+        assert_eq!(String::from_utf8(field(5)).unwrap(), "Zoë");
+        assert_eq!(i32::from_be_bytes(field(6).try_into().unwrap()), 1);
+        assert_eq!(i64::from_be_bytes(field(7).try_into().unwrap()), -1_000_000);
+        assert_eq!(roaring::RoaringBitmap::deserialize_from(field(8).as_slice()).unwrap().iter().collect::<Vec<u32>>(), vec![3, 5]);
+
+        let mut nulls = vec![None; 9];
+        nulls[0] = Some(7i64.to_be_bytes().to_vec());
+        assert_eq!(rows[1], nulls);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::errors::DataGenError::WeirdCase;
+use crate::errors::*;
+use crate::fakegen;
+use crate::schema::{Column, Schema};
+use crate::sinks::Sink;
+use crate::DValue;
+use std::io::Write;
+
+/// Shape of the generated SQL script
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SqlOptions {
+    /// Rows per multi-row `INSERT` statement
+    pub rows_per_statement: usize,
+    /// Wraps the script in `BEGIN;` and `COMMIT;`
+    pub transaction: bool,
+    /// Appends `ON CONFLICT DO NOTHING` to every statement
+    pub on_conflict_do_nothing: bool,
+}
+
+impl Default for SqlOptions {
+    fn default() -> Self {
+        SqlOptions { rows_per_statement: 1000, transaction: false, on_conflict_do_nothing: false }
+    }
+}
+
+/// Writes a PostgreSQL script of multi-row `INSERT INTO <dataset> (<columns>) VALUES ...` statements,
+/// loadable with `psql -f`. Values are rendered as literals that Postgres coerces to the column types.
+/// The script starts by turning `standard_conforming_strings` on, so that the literals load unchanged
+/// whatever the server setting.
+pub struct SqlSink<W: Write> {
+    schema: Schema,
+    options: SqlOptions,
+    /// `INSERT INTO ... VALUES` prefix of every statement
+    insert: String,
+    rows: Vec<String>,
+    writer: W,
+}
+
+pub fn sink<W: Write>(schema: Schema, mut w: W, options: SqlOptions) -> DataGenResult<SqlSink<W>> {
+    if options.rows_per_statement == 0 {
+        return Err(WeirdCase { message: "Rows per statement must be positive".to_string() });
+    }
+    let columns: Vec<String> = schema.dataset.columns.iter().map(|column| quote_identifier(&column.name)).collect();
+    let insert = format!("INSERT INTO {} ({}) VALUES\n", quote_identifier(&schema.dataset.name), columns.join(", "));
+    w.write_all(b"SET standard_conforming_strings = on;\n")?;
+    if options.transaction {
+        w.write_all(b"BEGIN;\n")?;
+    }
+    Ok(SqlSink { schema, options, insert, rows: Vec::with_capacity(options.rows_per_statement), writer: w })
+}
+
+impl<W: Write> SqlSink<W> {
+    fn flush_statement(&mut self) -> DataGenResult<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let mut statement = self.insert.clone();
+        statement.push_str(&self.rows.join(",\n"));
+        if self.options.on_conflict_do_nothing {
+            statement.push_str("\nON CONFLICT DO NOTHING");
+        }
+        statement.push_str(";\n");
+        self.writer.write_all(statement.as_bytes())?;
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for SqlSink<W> {
+    fn write(&mut self, value: DValue) -> Result<(), DataGenError> {
+        match value {
+            DValue::Record(vec) => {
+                let columns = &self.schema.dataset.columns;
+                if vec.len() != columns.len() {
+                    return Err(WeirdCase {
+                        message: format!("Record of {} fields written to table {} of {} columns", vec.len(), self.schema.dataset.name, columns.len())
+                    });
+                }
+                let literals = vec.into_iter().zip(columns.iter())
+                    .map(|((_key, value), column)| dvalue_to_sql(column, value))
+                    .collect::<DataGenResult<Vec<String>>>()?;
+                self.rows.push(format!("({})", literals.join(", ")));
+                if self.rows.len() >= self.options.rows_per_statement {
+                    self.flush_statement()?;
+                }
+                Ok(())
+            }
+            _ => Err(WeirdCase { message: format!("The 'value' parameters received at the SqlSink is not a Record. Value found was : {:?}", value) })
+        }
+    }
+
+    fn finish(&mut self) -> DataGenResult<()> {
+        self.flush_statement()?;
+        if self.options.transaction {
+            self.writer.write_all(b"COMMIT;\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// SQL literal of a value. Dates and datetimes are rendered in ISO format whatever the column format,
+/// so they do not depend on the server's `DateStyle`.
+#[rustfmt::skip]
+pub(crate) fn dvalue_to_sql(column: &Column, value: DValue) -> DataGenResult<String> {
+    use DValue::*;
+    let literal = match value {
+        Null            => "NULL".to_string(),
+        Boolean(val)    => if val { "TRUE".to_string() } else { "FALSE".to_string() },
+        Int(val)        => val.to_string(),
+        Long(val)       => val.to_string(),
+        Float(val) if val.is_finite()   => val.to_string(),
+        Double(val) if val.is_finite()  => val.to_string(),
+        Float(val)      => non_finite_literal(val as f64),
+        Double(val)     => non_finite_literal(val),
+        Bytes(val)      => quote_literal(&crate::sinks::csv_sink::bytea_hex(&val)),
+        Str(val)        => quote_literal(&val),
+        Date(val) | DateTime(val) => quote_literal(&fakegen::iso_date(column, &val)?),
+        IdSet(val)      => quote_literal(&format!("{{{}}}", val.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(","))),
+        Record(_)       => return Err(WeirdCase { message: format!("Column {} holds a nested record", column.name) }),
+    };
+    Ok(literal)
+}
+
+/// Non-finite floats are only valid as quoted literals
+fn non_finite_literal(val: f64) -> String {
+    if val.is_nan() {
+        "'NaN'".to_string()
+    } else if val > 0.0 {
+        "'Infinity'".to_string()
+    } else {
+        "'-Infinity'".to_string()
+    }
+}
+
+/// Standard-conforming string literal: single quotes doubled, backslashes kept as is. Only valid with
+/// `standard_conforming_strings` on, which the SQL script sets.
+pub(crate) fn quote_literal(val: &str) -> String {
+    format!("'{}'", val.replace('\'', "''"))
+}
+
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::from(r#"name: sql_schema
+dataset:
+  name: people
+  columns:
+    - {name: id, dtype: serial}
+    - {name: name, dtype: string}
+    - {name: dob, dtype: date, format: "%d/%m/%Y"}
+    - {name: adult, dtype: boolean}
+    - {name: score, dtype: double}
+"#).unwrap()
+    }
+
+    fn record(id: i64, name: &str) -> DValue {
+        DValue::Record(vec![
+            ("id".to_string(), DValue::Long(id)),
+            ("name".to_string(), DValue::Str(name.to_string())),
+            ("dob".to_string(), DValue::Date("02/01/2014".to_string())),
+            ("adult".to_string(), DValue::Boolean(true)),
+            ("score".to_string(), DValue::Double(f64::NAN)),
+        ])
+    }
+
+    #[test]
+    fn generate_batched_insert_statements() {
+        let mut vec: Vec<u8> = Vec::new();
+        {
+            let options = SqlOptions { rows_per_statement: 2, transaction: false, on_conflict_do_nothing: false };
+            let mut sink = sink(schema(), &mut vec, options).unwrap();
+            sink.write(record(1, "O'Brien")).unwrap();
+            sink.write(record(2, "back\\slash")).unwrap();
+            sink.write(DValue::Record(vec![
+                ("id".to_string(), DValue::Long(3)),
+                ("name".to_string(), DValue::Null),
+                ("dob".to_string(), DValue::Null),
+                ("adult".to_string(), DValue::Boolean(false)),
+                ("score".to_string(), DValue::Double(0.5)),
+            ])).unwrap();
+            sink.finish().unwrap();
+        }
+        pretty_assertions::assert_eq!(
+            r#"SET standard_conforming_strings = on;
+INSERT INTO "people" ("id", "name", "dob", "adult", "score") VALUES
+(1, 'O''Brien', '2014-01-02', TRUE, 'NaN'),
+(2, 'back\slash', '2014-01-02', TRUE, 'NaN');
+INSERT INTO "people" ("id", "name", "dob", "adult", "score") VALUES
+(3, NULL, NULL, FALSE, 0.5);
+"#,
+            String::from_utf8(vec).unwrap()
+        );
+    }
+
+    #[test]
+    fn reject_records_not_matching_the_columns() {
+        let mut vec: Vec<u8> = Vec::new();
+        let mut sink = sink(schema(), &mut vec, SqlOptions::default()).unwrap();
+        let short = DValue::Record(vec![("id".to_string(), DValue::Long(1)), ("name".to_string(), DValue::Str("Jason".to_string()))]);
+        assert_eq!(sink.write(short).unwrap_err().to_string(), "Record of 2 fields written to table people of 5 columns");
+        sink.finish().unwrap();
+        assert_eq!(String::from_utf8(vec).unwrap(), "SET standard_conforming_strings = on;\n");
+    }
+
+    #[test]
+    fn generate_transaction_with_on_conflict() {
+        let mut vec: Vec<u8> = Vec::new();
+        {
+            let options = SqlOptions { rows_per_statement: 10, transaction: true, on_conflict_do_nothing: true };
+            let mut sink = sink(schema(), &mut vec, options).unwrap();
+            sink.write(record(1, "Jason")).unwrap();
+            sink.finish().unwrap();
+        }
+        pretty_assertions::assert_eq!(
+            r#"SET standard_conforming_strings = on;
+BEGIN;
+INSERT INTO "people" ("id", "name", "dob", "adult", "score") VALUES
+(1, 'Jason', '2014-01-02', TRUE, 'NaN')
+ON CONFLICT DO NOTHING;
+COMMIT;
+"#,
+            String::from_utf8(vec).unwrap()
+        );
+    }
+}